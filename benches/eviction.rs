@@ -0,0 +1,109 @@
+use criterion::{criterion_group, criterion_main, Bencher, BenchmarkId, Criterion, Throughput};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use tempfile::tempdir;
+
+use britt_marie::{EvictionPolicy, HashIndex, HashOps, IndexOps, RawStore};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const POLICIES: [EvictionPolicy; 4] = [
+    EvictionPolicy::Probe,
+    EvictionPolicy::Clock,
+    EvictionPolicy::Lru { samples: 8 },
+    EvictionPolicy::Lfu { samples: 8 },
+];
+const SKEWS: [f64; 2] = [0.8, 1.2];
+const CAPACITY: usize = 1024;
+const MOD_FACTOR: f32 = 0.5;
+const TOTAL_KEYS: u64 = 10000;
+const TOTAL_OPERATIONS: u64 = 10000;
+/// Share of operations that are updates, the rest are reads
+const UPDATE_RATIO: f64 = 0.2;
+const NAMESPACE: &[u8] = b"bench";
+
+/// Operations of a skewed workload, as (key, is_update)
+type Workload = Vec<(u64, bool)>;
+
+static WORKLOADS: Lazy<Vec<Workload>> =
+    Lazy::new(|| SKEWS.iter().map(|&skew| zipf_workload(skew)).collect());
+
+/// Draws keys from a Zipf distribution with exponent `skew`
+///
+/// Key ranks are shuffled over the key space so that hot keys do not share a
+/// neighbourhood in the RawTable.
+fn zipf_workload(skew: f64) -> Workload {
+    let mut rng = rand::thread_rng();
+    let mut cdf = Vec::with_capacity(TOTAL_KEYS as usize);
+    let mut total = 0.0;
+    for rank in 1..=TOTAL_KEYS {
+        total += 1.0 / (rank as f64).powf(skew);
+        cdf.push(total);
+    }
+    let mut keys: Vec<u64> = (0..TOTAL_KEYS).collect();
+    for i in (1..keys.len()).rev() {
+        keys.swap(i, rng.gen_range(0, i + 1));
+    }
+    (0..TOTAL_OPERATIONS)
+        .map(|_| {
+            let x = rng.gen::<f64>() * total;
+            let rank = match cdf.binary_search_by(|c| c.partial_cmp(&x).unwrap()) {
+                Ok(rank) | Err(rank) => usize::min(rank, cdf.len() - 1),
+            };
+            (keys[rank], rng.gen::<f64>() < UPDATE_RATIO)
+        })
+        .collect()
+}
+
+fn eviction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eviction");
+    group.throughput(Throughput::Elements(TOTAL_OPERATIONS));
+    for (skew, workload) in SKEWS.iter().zip(WORKLOADS.iter()) {
+        for policy in POLICIES.iter() {
+            let temp_dir = tempdir().unwrap();
+            let mut hash_index = setup_index(temp_dir.path().to_str().unwrap(), *policy);
+            run_workload(&mut hash_index, workload);
+            println!(
+                "eviction: {:?}, skew: {}, hit ratio: {:.3}",
+                policy,
+                skew,
+                hash_index.hit_ratio()
+            );
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", policy), format!("skew: {}", skew)),
+                workload,
+                |b, workload| skewed_workload(b, &mut hash_index, workload),
+            );
+        }
+    }
+    group.finish()
+}
+
+fn setup_index(path: &str, policy: EvictionPolicy) -> HashIndex<u64, u64> {
+    let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+    let mut hash_index: HashIndex<u64, u64> =
+        HashIndex::new(NAMESPACE, CAPACITY, MOD_FACTOR, raw_store).with_eviction_policy(policy);
+    for id in 0..TOTAL_KEYS {
+        hash_index.put(id, id);
+    }
+    hash_index.persist().unwrap();
+    hash_index
+}
+
+fn run_workload(hash_index: &mut HashIndex<u64, u64>, workload: &[(u64, bool)]) {
+    for &(id, update) in workload.iter() {
+        if update {
+            hash_index.rmw(&id, |v| *v += 1);
+        } else {
+            criterion::black_box(hash_index.get(&id));
+        }
+    }
+}
+
+fn skewed_workload(b: &mut Bencher, hash_index: &mut HashIndex<u64, u64>, workload: &Workload) {
+    b.iter(|| run_workload(hash_index, workload));
+}
+
+criterion_group!(benches, eviction);
+criterion_main!(benches);
@@ -4,6 +4,8 @@ use once_cell::sync::Lazy;
 use rand::Rng;
 use tempfile::tempdir;
 
+#[cfg(feature = "compression")]
+use britt_marie::Compression;
 use britt_marie::{HashIndex, HashOps, RawStore};
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -12,6 +14,7 @@ const MOD_FACTORS: [f32; 3] = [0.3, 0.5, 0.8];
 const CAPACITY: [usize; 3] = [512, 4096, 10024];
 const TOTAL_KEYS: u64 = 10000;
 const TOTAL_OPERATIONS: u64 = 1000;
+const NAMESPACE: &[u8] = b"bench";
 
 static RANDOM_INDEXES: Lazy<Vec<u64>> = Lazy::new(|| {
     let mut rng = rand::thread_rng();
@@ -120,6 +123,15 @@ fn hash(c: &mut Criterion) {
     // TODO: merge operator
     group.bench_function("RMW SmallStruct RawStore", rmw_raw_store_small);
     group.bench_function("RMW LargeStruct RawStore", rmw_raw_store_large);
+    #[cfg(feature = "compression")]
+    {
+        group.bench_function("Insert LargeStruct RawStore LZ4", |b| {
+            insert_raw_store_large_compressed(b, Compression::Lz4)
+        });
+        group.bench_function("Insert LargeStruct RawStore Zstd", |b| {
+            insert_raw_store_large_compressed(b, Compression::zstd(0))
+        });
+    }
 
     group.finish()
 }
@@ -129,7 +141,7 @@ fn insert_small(b: &mut Bencher, capacity: usize, mod_factor: f32) {
     let path = temp_dir.path().to_str().unwrap();
     let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
     let mut hash_index: HashIndex<u64, SmallStruct> =
-        HashIndex::new(capacity, mod_factor, raw_store.clone());
+        HashIndex::new(NAMESPACE, capacity, mod_factor, raw_store.clone());
 
     b.iter(|| {
         for id in RANDOM_INDEXES.iter() {
@@ -145,7 +157,7 @@ fn insert_raw_store_small(b: &mut Bencher) {
 
     b.iter(|| {
         for id in RANDOM_INDEXES.iter() {
-            let _ = raw_store.put(&*id, &SmallStruct::new());
+            let _ = raw_store.put(NAMESPACE, &*id, &SmallStruct::new());
         }
     });
 }
@@ -155,7 +167,7 @@ fn insert_large(b: &mut Bencher, capacity: usize, mod_factor: f32) {
     let path = temp_dir.path().to_str().unwrap();
     let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
     let mut hash_index: HashIndex<u64, LargeStruct> =
-        HashIndex::new(capacity, mod_factor, raw_store.clone());
+        HashIndex::new(NAMESPACE, capacity, mod_factor, raw_store.clone());
 
     b.iter(|| {
         for id in RANDOM_INDEXES.iter() {
@@ -171,7 +183,21 @@ fn insert_raw_store_large(b: &mut Bencher) {
 
     b.iter(|| {
         for id in RANDOM_INDEXES.iter() {
-            let _ = raw_store.put(&*id, &LargeStruct::new());
+            let _ = raw_store.put(NAMESPACE, &*id, &LargeStruct::new());
+        }
+    });
+}
+
+#[cfg(feature = "compression")]
+fn insert_raw_store_large_compressed(b: &mut Bencher, compression: Compression) {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let mut raw_store = RawStore::new(path);
+    raw_store.set_compression(NAMESPACE, compression).unwrap();
+
+    b.iter(|| {
+        for id in RANDOM_INDEXES.iter() {
+            let _ = raw_store.put(NAMESPACE, &*id, &LargeStruct::new());
         }
     });
 }
@@ -181,7 +207,7 @@ fn rmw_small(b: &mut Bencher, capacity: usize, mod_factor: f32) {
     let path = temp_dir.path().to_str().unwrap();
     let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
     let mut hash_index: HashIndex<u64, SmallStruct> =
-        HashIndex::new(capacity, mod_factor, raw_store.clone());
+        HashIndex::new(NAMESPACE, capacity, mod_factor, raw_store.clone());
     for i in 0..TOTAL_KEYS {
         hash_index.put(i, SmallStruct::new());
     }
@@ -202,7 +228,7 @@ fn rmw_large(b: &mut Bencher, capacity: usize, mod_factor: f32) {
     let path = temp_dir.path().to_str().unwrap();
     let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
     let mut hash_index: HashIndex<u64, LargeStruct> =
-        HashIndex::new(capacity, mod_factor, raw_store.clone());
+        HashIndex::new(NAMESPACE, capacity, mod_factor, raw_store.clone());
     for i in 0..TOTAL_KEYS {
         hash_index.put(i, LargeStruct::new());
     }
@@ -223,14 +249,14 @@ fn rmw_raw_store_small(b: &mut Bencher) {
     let path = temp_dir.path().to_str().unwrap();
     let mut raw_store = RawStore::new(path);
     for i in 0..TOTAL_KEYS {
-        let _ = raw_store.put(&i, &SmallStruct::new());
+        let _ = raw_store.put(NAMESPACE, &i, &SmallStruct::new());
     }
     b.iter(|| {
         for i in RANDOM_INDEXES.iter() {
-            let val: Option<SmallStruct> = raw_store.get(i).unwrap();
+            let val: Option<SmallStruct> = raw_store.get(NAMESPACE, i).unwrap();
             let mut new_val = val.unwrap();
             new_val.x2 = new_val.x2 + 10;
-            assert_eq!(raw_store.put(i, &new_val).is_ok(), true);
+            assert_eq!(raw_store.put(NAMESPACE, i, &new_val).is_ok(), true);
         }
     });
 }
@@ -240,14 +266,14 @@ fn rmw_raw_store_large(b: &mut Bencher) {
     let path = temp_dir.path().to_str().unwrap();
     let mut raw_store = RawStore::new(path);
     for i in 0..TOTAL_KEYS {
-        let _ = raw_store.put(&i, &LargeStruct::new());
+        let _ = raw_store.put(NAMESPACE, &i, &LargeStruct::new());
     }
     b.iter(|| {
         for i in RANDOM_INDEXES.iter() {
-            let val: Option<LargeStruct> = raw_store.get(i).unwrap();
+            let val: Option<LargeStruct> = raw_store.get(NAMESPACE, i).unwrap();
             let mut new_val = val.unwrap();
             new_val.x2 = new_val.x2 + 10;
-            assert_eq!(raw_store.put(i, &new_val).is_ok(), true);
+            assert_eq!(raw_store.put(NAMESPACE, i, &new_val).is_ok(), true);
         }
     });
 }
@@ -257,7 +283,7 @@ fn random_get_small(b: &mut Bencher, capacity: usize, mod_factor: f32) {
     let path = temp_dir.path().to_str().unwrap();
     let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
     let mut hash_index: HashIndex<u64, SmallStruct> =
-        HashIndex::new(capacity, mod_factor, raw_store.clone());
+        HashIndex::new(NAMESPACE, capacity, mod_factor, raw_store.clone());
     for i in 0..TOTAL_KEYS {
         hash_index.put(i, SmallStruct::new());
     }
@@ -273,7 +299,7 @@ fn random_get_large(b: &mut Bencher, capacity: usize, mod_factor: f32) {
     let path = temp_dir.path().to_str().unwrap();
     let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
     let mut hash_index: HashIndex<u64, LargeStruct> =
-        HashIndex::new(capacity, mod_factor, raw_store.clone());
+        HashIndex::new(NAMESPACE, capacity, mod_factor, raw_store.clone());
     for i in 0..TOTAL_KEYS {
         hash_index.put(i, LargeStruct::new());
     }
@@ -289,11 +315,11 @@ fn raw_store_random_small_get(b: &mut Bencher) {
     let path = temp_dir.path().to_str().unwrap();
     let mut raw_store = RawStore::new(path);
     for i in 0..TOTAL_KEYS {
-        let _ = raw_store.put(&i, &SmallStruct::new());
+        let _ = raw_store.put(NAMESPACE, &i, &SmallStruct::new());
     }
     b.iter(|| {
         for i in RANDOM_INDEXES.iter() {
-            let data: Option<SmallStruct> = raw_store.get(i).unwrap();
+            let data: Option<SmallStruct> = raw_store.get(NAMESPACE, i).unwrap();
             assert_eq!(data.is_some(), true);
         }
     });
@@ -304,11 +330,11 @@ fn raw_store_random_large_get(b: &mut Bencher) {
     let path = temp_dir.path().to_str().unwrap();
     let mut raw_store = RawStore::new(path);
     for i in 0..TOTAL_KEYS {
-        let _ = raw_store.put(&i, &LargeStruct::new());
+        let _ = raw_store.put(NAMESPACE, &i, &LargeStruct::new());
     }
     b.iter(|| {
         for i in RANDOM_INDEXES.iter() {
-            let data: Option<LargeStruct> = raw_store.get(i).unwrap();
+            let data: Option<LargeStruct> = raw_store.get(NAMESPACE, i).unwrap();
             assert_eq!(data.is_some(), true);
         }
     });
@@ -37,11 +37,11 @@ fn counter_bench(b: &mut Bencher, mut index: ValueIndex<u64>) {
 // TODO: Should probably move this to RocksDB merge operator..
 fn raw_store_rolling_count(b: &mut Bencher) {
     let mut raw_store = RawStore::new("/tmp/rolling");
-    let key: Vec<u8> = String::from("_rolling_counter").into();
+    let namespace = b"_rolling_counter";
     b.iter(|| {
-        let curr: Option<u64>= raw_store.get(&key).unwrap();
+        let curr: Option<u64>= raw_store.get(namespace, &()).unwrap();
         let new_curr = curr.map_or_else(|| 0, |v| v + 1);
-        let _ = raw_store.put(&key, &new_curr);
+        let _ = raw_store.put(namespace, &(), &new_curr);
     });
 }
 
@@ -0,0 +1,476 @@
+use proc_macro2::{Span, TokenStream};
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+/// Attributes supported through `#[britt_marie(..)]`
+#[derive(Default)]
+struct Attrs {
+    tag: Option<u32>,
+    skip: bool,
+    default: bool,
+    reserved: Vec<u32>,
+}
+
+/// Names of the attributes supported through `#[britt_marie(..)]`
+const ATTRIBUTES: [&str; 4] = ["tag", "skip", "default", "reserved"];
+
+/// Items that `#[britt_marie(..)]` attributes may be placed on
+#[derive(Clone, Copy)]
+enum Target {
+    Container,
+    Field,
+    Variant,
+}
+
+impl Target {
+    /// Returns whether the attribute `name` has an effect on the target
+    fn allows(self, name: &str) -> bool {
+        match self {
+            Target::Container => name == "reserved",
+            Target::Field => name == "tag" || name == "skip",
+            Target::Variant => name == "tag" || name == "default",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Target::Container => "a struct or enum",
+            Target::Field => "a field",
+            Target::Variant => "an enum variant",
+        }
+    }
+}
+
+fn parse_tag(lit: &Lit) -> u32 {
+    let tag = match lit {
+        Lit::Int(i) => i.base10_parse::<u32>().ok(),
+        Lit::Str(s) => s.value().trim().parse::<u32>().ok(),
+        _ => None,
+    };
+    match tag {
+        Some(tag) if tag > 0 && tag < (1 << 29) => tag,
+        _ => panic!("britt_marie tags must be integers between 1 and 2^29 - 1"),
+    }
+}
+
+fn parse_attrs(attrs: &[Attribute], target: Target) -> Attrs {
+    let mut res = Attrs::default();
+    for attr in attrs.iter().filter(|a| a.path.is_ident("britt_marie")) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            _ => panic!("Expected #[britt_marie(..)]"),
+        };
+        for nested in list.nested.iter() {
+            let name = match nested {
+                NestedMeta::Meta(meta) => meta.path().get_ident().map(|i| i.to_string()),
+                NestedMeta::Lit(_) => None,
+            };
+            if let Some(name) = name {
+                if ATTRIBUTES.contains(&&*name) && !target.allows(&name) {
+                    panic!(
+                        "#[britt_marie({})] is not valid on {}",
+                        name,
+                        target.describe()
+                    );
+                }
+            }
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("tag") => {
+                    res.tag = Some(parse_tag(&nv.lit));
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("reserved") => {
+                    if let Lit::Str(s) = &nv.lit {
+                        for tag in s.value().split(',') {
+                            let tag = tag.trim().parse::<u32>().unwrap_or_else(|_| {
+                                panic!("Reserved tags are expected as a list, e.g., \"2, 5\"")
+                            });
+                            res.reserved.push(tag);
+                        }
+                    } else {
+                        panic!("Reserved tags are expected as a list, e.g., \"2, 5\"");
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("skip") => res.skip = true,
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("default") => res.default = true,
+                _ => panic!("Unknown britt_marie attribute"),
+            }
+        }
+    }
+    res
+}
+
+/// A field that is part of the encoding
+struct TaggedField {
+    /// Binding used when destructuring
+    binding: Ident,
+    /// Accessor on `self`, e.g., `x1` or `0`
+    member: syn::Member,
+    tag: u32,
+    skip: bool,
+}
+
+fn tagged_fields(fields: &Fields, reserved: &[u32]) -> Vec<TaggedField> {
+    let mut used = Vec::new();
+    let tagged: Vec<TaggedField> = fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            let attrs = parse_attrs(&field.attrs, Target::Field);
+            let tag = attrs.tag.unwrap_or(idx as u32 + 1);
+            let (binding, member) = match field.ident {
+                Some(ref ident) => (ident.clone(), syn::Member::Named(ident.clone())),
+                None => (
+                    Ident::new(&format!("__field{}", idx), Span::call_site()),
+                    syn::Member::Unnamed(syn::Index::from(idx)),
+                ),
+            };
+            if !attrs.skip {
+                if used.contains(&tag) {
+                    panic!("Duplicate britt_marie tag {}", tag);
+                }
+                if reserved.contains(&tag) {
+                    panic!("britt_marie tag {} is reserved", tag);
+                }
+                used.push(tag);
+            }
+            TaggedField {
+                binding,
+                member,
+                tag,
+                skip: attrs.skip,
+            }
+        })
+        .collect();
+    tagged
+}
+
+/// Expands a fields pattern, e.g., `{ x, y }` or `(__field0, __field1)`
+fn fields_pattern(fields: &Fields, tagged: &[TaggedField]) -> TokenStream {
+    let bindings = tagged.iter().map(|f| &f.binding);
+    match fields {
+        Fields::Named(_) => quote! { { #(#bindings),* } },
+        Fields::Unnamed(_) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => quote! {},
+    }
+}
+
+/// Expands a constructor where every field is set to its default
+fn fields_default(fields: &Fields) -> TokenStream {
+    match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| &f.ident);
+            quote! { { #(#idents: ::std::default::Default::default()),* } }
+        }
+        Fields::Unnamed(unnamed) => {
+            let defaults = unnamed
+                .unnamed
+                .iter()
+                .map(|_| quote! { ::std::default::Default::default() });
+            quote! { ( #(#defaults),* ) }
+        }
+        Fields::Unit => quote! {},
+    }
+}
+
+/// Expands a debug formatter for a set of bindings
+fn fields_debug(name: &str, fields: &Fields, tagged: &[TaggedField]) -> TokenStream {
+    match fields {
+        Fields::Named(_) => {
+            let entries = tagged.iter().map(|f| {
+                let binding = &f.binding;
+                let name = binding.to_string();
+                quote! { .field(#name, #binding) }
+            });
+            quote! { f.debug_struct(#name) #(#entries)* .finish() }
+        }
+        Fields::Unnamed(_) => {
+            let entries = tagged.iter().map(|f| {
+                let binding = &f.binding;
+                quote! { .field(#binding) }
+            });
+            quote! { f.debug_tuple(#name) #(#entries)* .finish() }
+        }
+        Fields::Unit => quote! { f.write_str(#name) },
+    }
+}
+
+pub fn derive(item: DeriveInput) -> TokenStream {
+    let name = &item.ident;
+    let name_str = name.to_string();
+    let container = parse_attrs(&item.attrs, Target::Container);
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+
+    let (encode_raw, merge_field, encoded_len, default, debug) = match item.data {
+        Data::Struct(ref s) => {
+            let tagged = tagged_fields(&s.fields, &container.reserved);
+            let pattern = fields_pattern(&s.fields, &tagged);
+            let encoded: Vec<&TaggedField> = tagged.iter().filter(|f| !f.skip).collect();
+
+            let encodes = encoded.iter().map(|f| {
+                let (member, tag) = (&f.member, f.tag);
+                quote! { ::britt_marie::data::field::Field::encode(&self.#member, #tag, buf); }
+            });
+            let merges = encoded.iter().map(|f| {
+                let (member, tag) = (&f.member, f.tag);
+                quote! {
+                    #tag => ::britt_marie::data::field::Field::merge(&mut self.#member, wire_type, buf, ctx),
+                }
+            });
+            let lens = encoded.iter().map(|f| {
+                let (member, tag) = (&f.member, f.tag);
+                quote! { + ::britt_marie::data::field::Field::encoded_len(&self.#member, #tag) }
+            });
+            let debug = fields_debug(&name_str, &s.fields, &tagged);
+            let ctor = fields_default(&s.fields);
+
+            (
+                quote! { #(#encodes)* },
+                quote! {
+                    match tag {
+                        #(#merges)*
+                        _ => ::britt_marie::data::field::skip_field(wire_type, tag, buf, ctx),
+                    }
+                },
+                quote! { 0 #(#lens)* },
+                quote! { #name #ctor },
+                quote! {
+                    let #name #pattern = self;
+                    #debug
+                },
+            )
+        }
+        Data::Enum(ref e) => {
+            if e.variants.is_empty() {
+                panic!("#[derive(BrittMarieData)] needs at least one enum variant");
+            }
+            let mut used = Vec::new();
+            let mut default_variant = None;
+            let mut explicit_default = false;
+            let mut encodes = Vec::new();
+            let mut merges = Vec::new();
+            let mut lens = Vec::new();
+            let mut debugs = Vec::new();
+
+            for (idx, variant) in e.variants.iter().enumerate() {
+                let attrs = parse_attrs(&variant.attrs, Target::Variant);
+                let tag = attrs.tag.unwrap_or(idx as u32 + 1);
+                if used.contains(&tag) {
+                    panic!("Duplicate britt_marie tag {}", tag);
+                }
+                if container.reserved.contains(&tag) {
+                    panic!("britt_marie tag {} is reserved", tag);
+                }
+                used.push(tag);
+
+                let ident = &variant.ident;
+                let ctor = fields_default(&variant.fields);
+                // The first variant is the default unless another one is marked explicitly
+                if attrs.default {
+                    if explicit_default {
+                        panic!("Only one variant can be marked #[britt_marie(default)]");
+                    }
+                    explicit_default = true;
+                    default_variant = Some(quote! { #name::#ident #ctor });
+                } else if idx == 0 {
+                    default_variant = Some(quote! { #name::#ident #ctor });
+                }
+
+                let tagged = tagged_fields(&variant.fields, &[]);
+                let pattern = fields_pattern(&variant.fields, &tagged);
+                let encoded: Vec<&TaggedField> = tagged.iter().filter(|f| !f.skip).collect();
+                let field_lens: Vec<TokenStream> = encoded
+                    .iter()
+                    .map(|f| {
+                        let (binding, tag) = (&f.binding, f.tag);
+                        quote! { + ::britt_marie::data::field::Field::encoded_len(#binding, #tag) }
+                    })
+                    .collect();
+                let field_encodes = encoded.iter().map(|f| {
+                    let (binding, tag) = (&f.binding, f.tag);
+                    quote! { ::britt_marie::data::field::Field::encode(#binding, #tag, buf); }
+                });
+                let field_merges = encoded.iter().map(|f| {
+                    let (binding, tag) = (&f.binding, f.tag);
+                    quote! {
+                        #tag => ::britt_marie::data::field::Field::merge(#binding, wire_type, buf, ctx),
+                    }
+                });
+                let any_pattern = match variant.fields {
+                    Fields::Named(_) => quote! { { .. } },
+                    Fields::Unnamed(_) => quote! { (..) },
+                    Fields::Unit => quote! {},
+                };
+
+                encodes.push(quote! {
+                    #[allow(unused_variables)]
+                    #name::#ident #pattern => {
+                        let len = 0 #(#field_lens)*;
+                        ::britt_marie::data::field::encode_nested_header(#tag, len, buf);
+                        #(#field_encodes)*
+                    }
+                });
+                lens.push(quote! {
+                    #[allow(unused_variables)]
+                    #name::#ident #pattern => {
+                        ::britt_marie::data::field::encoded_len_nested(#tag, 0 #(#field_lens)*)
+                    }
+                });
+                merges.push(quote! {
+                    #tag => {
+                        if !matches!(self, #name::#ident #any_pattern) {
+                            *self = #name::#ident #ctor;
+                        }
+                        match self {
+                            #[allow(unused_variables)]
+                            #name::#ident #pattern => ::britt_marie::data::field::merge_nested(
+                                wire_type,
+                                buf,
+                                ctx,
+                                |tag, wire_type, buf, ctx| match tag {
+                                    #(#field_merges)*
+                                    _ => ::britt_marie::data::field::skip_field(wire_type, tag, buf, ctx),
+                                },
+                            ),
+                            _ => unreachable!(),
+                        }
+                    }
+                });
+                let debug = fields_debug(&ident.to_string(), &variant.fields, &tagged);
+                debugs.push(quote! {
+                    #name::#ident #pattern => { #debug }
+                });
+            }
+
+            (
+                quote! {
+                    match self {
+                        #(#encodes)*
+                    }
+                },
+                quote! {
+                    match tag {
+                        #(#merges)*
+                        _ => ::britt_marie::data::field::skip_field(wire_type, tag, buf, ctx),
+                    }
+                },
+                quote! {
+                    match self {
+                        #(#lens)*
+                    }
+                },
+                default_variant.unwrap(),
+                quote! {
+                    match self {
+                        #(#debugs)*
+                    }
+                },
+            )
+        }
+        Data::Union(_) => panic!("#[derive(BrittMarieData)] does not support unions"),
+    };
+
+    quote! {
+        impl #impl_generics ::britt_marie::prost::Message for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn encode_raw<B>(&self, buf: &mut B)
+            where
+                B: ::britt_marie::prost::bytes::BufMut,
+            {
+                #encode_raw
+            }
+
+            #[allow(unused_variables)]
+            fn merge_field<B>(
+                &mut self,
+                tag: u32,
+                wire_type: ::britt_marie::prost::encoding::WireType,
+                buf: &mut B,
+                ctx: ::britt_marie::prost::encoding::DecodeContext,
+            ) -> ::std::result::Result<(), ::britt_marie::prost::DecodeError>
+            where
+                B: ::britt_marie::prost::bytes::Buf,
+            {
+                #merge_field
+            }
+
+            #[inline]
+            fn encoded_len(&self) -> usize {
+                #encoded_len
+            }
+
+            fn clear(&mut self) {
+                *self = ::std::default::Default::default();
+            }
+        }
+
+        impl #impl_generics ::std::default::Default for #name #ty_generics #where_clause {
+            fn default() -> Self {
+                #default
+            }
+        }
+
+        impl #impl_generics ::std::fmt::Debug for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                #debug
+            }
+        }
+
+        impl #impl_generics ::britt_marie::data::field::Field for #name #ty_generics #where_clause {
+            #[inline]
+            fn encode<B>(&self, tag: u32, buf: &mut B)
+            where
+                B: ::britt_marie::prost::bytes::BufMut,
+            {
+                if ::britt_marie::prost::Message::encoded_len(self) != 0 {
+                    ::britt_marie::data::field::encode_message(tag, self, buf);
+                }
+            }
+            #[inline]
+            fn merge<B>(
+                &mut self,
+                wire_type: ::britt_marie::prost::encoding::WireType,
+                buf: &mut B,
+                ctx: ::britt_marie::prost::encoding::DecodeContext,
+            ) -> ::std::result::Result<(), ::britt_marie::prost::DecodeError>
+            where
+                B: ::britt_marie::prost::bytes::Buf,
+            {
+                ::britt_marie::data::field::merge_message(wire_type, self, buf, ctx)
+            }
+            #[inline]
+            fn encoded_len(&self, tag: u32) -> usize {
+                if ::britt_marie::prost::Message::encoded_len(self) != 0 {
+                    ::britt_marie::data::field::encoded_len_message(tag, self)
+                } else {
+                    0
+                }
+            }
+        }
+
+        impl #impl_generics ::britt_marie::data::field::Repeated for #name #ty_generics #where_clause {
+            #[inline]
+            fn encode_repeated<B>(tag: u32, values: &[Self], buf: &mut B)
+            where
+                B: ::britt_marie::prost::bytes::BufMut,
+            {
+                ::britt_marie::data::field::encode_repeated_message(tag, values, buf);
+            }
+            #[inline]
+            fn merge_repeated<B>(
+                wire_type: ::britt_marie::prost::encoding::WireType,
+                values: &mut Vec<Self>,
+                buf: &mut B,
+                ctx: ::britt_marie::prost::encoding::DecodeContext,
+            ) -> ::std::result::Result<(), ::britt_marie::prost::DecodeError>
+            where
+                B: ::britt_marie::prost::bytes::Buf,
+            {
+                ::britt_marie::data::field::merge_repeated_message(wire_type, values, buf, ctx)
+            }
+            #[inline]
+            fn encoded_len_repeated(tag: u32, values: &[Self]) -> usize {
+                ::britt_marie::data::field::encoded_len_repeated_message(tag, values)
+            }
+        }
+    }
+}
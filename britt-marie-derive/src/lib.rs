@@ -7,6 +7,8 @@ extern crate quote;
 use proc_macro::TokenStream;
 use syn::{parse_macro_input, DeriveInput};
 
+mod data;
+
 #[proc_macro_derive(BrittMarie)]
 pub fn britt_marie(input: TokenStream) -> TokenStream {
     let item = parse_macro_input!(input as DeriveInput);
@@ -27,6 +29,12 @@ pub fn britt_marie(input: TokenStream) -> TokenStream {
             persist_quotes.push(field_gen);
         }
 
+        let mut enforce_quotes = Vec::new();
+        for (ident, _) in idents.iter() {
+            let field_gen = quote! { self.#ident.enforce_budget()?; };
+            enforce_quotes.push(field_gen);
+        }
+
         let mut field_getters = Vec::new();
         for (ident, ty) in idents.iter() {
             let field_gen = quote! { pub fn #ident(&mut self) -> &mut #ty { &mut self.#ident } };
@@ -41,6 +49,12 @@ pub fn britt_marie(input: TokenStream) -> TokenStream {
                         #(#persist_quotes)*
                         raw_store.borrow_mut().checkpoint()
                     }
+                    /// Shrinks the indexes that the memory budget of the RawStore asked to
+                    #[inline]
+                    pub fn enforce_budget(&mut self) -> Result<(), ::britt_marie::BrittMarieError> {
+                        #(#enforce_quotes)*
+                        Ok(())
+                    }
                     #(#field_getters)*
                 }
             }
@@ -51,3 +65,16 @@ pub fn britt_marie(input: TokenStream) -> TokenStream {
         panic!("#[derive(BrittMarie)] only works for structs");
     }
 }
+
+/// Derives an efficient encoder and decoder for a struct or enum
+///
+/// The generated [prost::Message] implementation makes the type usable as
+/// both Key and Value. Field tags default to the declaration order and can be
+/// pinned with `#[britt_marie(tag = N)]` to keep persisted data readable across
+/// versions. Fields marked `#[britt_marie(skip)]` are not stored, and
+/// `#[britt_marie(reserved = "2, 5")]` on the container rejects reuse of retired tags.
+#[proc_macro_derive(BrittMarieData, attributes(britt_marie))]
+pub fn britt_marie_data(input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as DeriveInput);
+    proc_macro::TokenStream::from(data::derive(item))
+}
@@ -0,0 +1,276 @@
+//! Field level encoding used by `#[derive(BrittMarieData)]`
+//!
+//! The derive macro generates a [prost::Message] implementation where every
+//! field is encoded through the [Field] trait. Scalars use the most compact
+//! protobuf wire representation (zigzag for signed integers, packed for repeated
+//! numerics) and default values are skipped, just like proto3.
+
+use prost::bytes::{Buf, BufMut};
+use prost::encoding::{self, DecodeContext, WireType};
+use prost::{DecodeError, Message};
+
+/// A type that can be encoded as a single tagged field
+pub trait Field: Default {
+    /// Encode the field with the given tag
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B);
+    /// Merge an encoded value into the field
+    fn merge<B: Buf>(
+        &mut self,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>;
+    /// Returns the encoded length of the field including its key
+    fn encoded_len(&self, tag: u32) -> usize;
+}
+
+/// A type that can be encoded as an element of a repeated field
+pub trait Repeated: Sized {
+    fn encode_repeated<B: BufMut>(tag: u32, values: &[Self], buf: &mut B);
+    fn merge_repeated<B: Buf>(
+        wire_type: WireType,
+        values: &mut Vec<Self>,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>;
+    fn encoded_len_repeated(tag: u32, values: &[Self]) -> usize;
+}
+
+macro_rules! scalar_field {
+    ($ty:ty, $proto_ty:ident) => {
+        impl Field for $ty {
+            #[inline]
+            fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+                if *self != <$ty>::default() {
+                    encoding::$proto_ty::encode(tag, self, buf);
+                }
+            }
+            #[inline]
+            fn merge<B: Buf>(
+                &mut self,
+                wire_type: WireType,
+                buf: &mut B,
+                ctx: DecodeContext,
+            ) -> Result<(), DecodeError> {
+                encoding::$proto_ty::merge(wire_type, self, buf, ctx)
+            }
+            #[inline]
+            fn encoded_len(&self, tag: u32) -> usize {
+                if *self != <$ty>::default() {
+                    encoding::$proto_ty::encoded_len(tag, self)
+                } else {
+                    0
+                }
+            }
+        }
+    };
+}
+
+macro_rules! packed_field {
+    ($ty:ty, $proto_ty:ident) => {
+        scalar_field!($ty, $proto_ty);
+
+        impl Repeated for $ty {
+            #[inline]
+            fn encode_repeated<B: BufMut>(tag: u32, values: &[Self], buf: &mut B) {
+                encoding::$proto_ty::encode_packed(tag, values, buf);
+            }
+            #[inline]
+            fn merge_repeated<B: Buf>(
+                wire_type: WireType,
+                values: &mut Vec<Self>,
+                buf: &mut B,
+                ctx: DecodeContext,
+            ) -> Result<(), DecodeError> {
+                encoding::$proto_ty::merge_repeated(wire_type, values, buf, ctx)
+            }
+            #[inline]
+            fn encoded_len_repeated(tag: u32, values: &[Self]) -> usize {
+                encoding::$proto_ty::encoded_len_packed(tag, values)
+            }
+        }
+    };
+}
+
+packed_field!(bool, bool);
+packed_field!(u32, uint32);
+packed_field!(u64, uint64);
+packed_field!(i32, sint32);
+packed_field!(i64, sint64);
+packed_field!(f32, float);
+packed_field!(f64, double);
+scalar_field!(String, string);
+scalar_field!(Vec<u8>, bytes);
+
+impl Repeated for String {
+    #[inline]
+    fn encode_repeated<B: BufMut>(tag: u32, values: &[Self], buf: &mut B) {
+        encoding::string::encode_repeated(tag, values, buf);
+    }
+    #[inline]
+    fn merge_repeated<B: Buf>(
+        wire_type: WireType,
+        values: &mut Vec<Self>,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        encoding::string::merge_repeated(wire_type, values, buf, ctx)
+    }
+    #[inline]
+    fn encoded_len_repeated(tag: u32, values: &[Self]) -> usize {
+        encoding::string::encoded_len_repeated(tag, values)
+    }
+}
+
+impl<T: Repeated> Field for Vec<T> {
+    #[inline]
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        T::encode_repeated(tag, self, buf);
+    }
+    #[inline]
+    fn merge<B: Buf>(
+        &mut self,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        T::merge_repeated(wire_type, self, buf, ctx)
+    }
+    #[inline]
+    fn encoded_len(&self, tag: u32) -> usize {
+        T::encoded_len_repeated(tag, self)
+    }
+}
+
+/// Optional fields are always encoded when set, even if they hold a default value.
+impl<T: Field> Field for Option<T> {
+    #[inline]
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if let Some(value) = self {
+            // Write an empty length-delimited field for defaults so that
+            // presence survives the roundtrip.
+            if value.encoded_len(tag) == 0 {
+                encoding::encode_key(tag, WireType::LengthDelimited, buf);
+                encoding::encode_varint(0, buf);
+            } else {
+                value.encode(tag, buf);
+            }
+        }
+    }
+    #[inline]
+    fn merge<B: Buf>(
+        &mut self,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        let value = self.get_or_insert_with(T::default);
+        if wire_type == WireType::LengthDelimited && buf.bytes().first() == Some(&0) {
+            // Either a presence marker or an empty length-delimited value,
+            // both of which decode into the default.
+            buf.advance(1);
+            Ok(())
+        } else {
+            value.merge(wire_type, buf, ctx)
+        }
+    }
+    #[inline]
+    fn encoded_len(&self, tag: u32) -> usize {
+        match self {
+            Some(value) => match value.encoded_len(tag) {
+                0 => encoding::key_len(tag) + 1,
+                len => len,
+            },
+            None => 0,
+        }
+    }
+}
+
+/// Encodes a nested message as a length-delimited field
+#[inline]
+pub fn encode_message<M: Message, B: BufMut>(tag: u32, msg: &M, buf: &mut B) {
+    encoding::message::encode(tag, msg, buf);
+}
+
+/// Merges a length-delimited nested message
+#[inline]
+pub fn merge_message<M: Message, B: Buf>(
+    wire_type: WireType,
+    msg: &mut M,
+    buf: &mut B,
+    ctx: DecodeContext,
+) -> Result<(), DecodeError> {
+    encoding::message::merge(wire_type, msg, buf, ctx)
+}
+
+/// Returns the encoded length of a nested message including its key
+#[inline]
+pub fn encoded_len_message<M: Message>(tag: u32, msg: &M) -> usize {
+    encoding::message::encoded_len(tag, msg)
+}
+
+/// Encodes a slice of nested messages as a repeated field
+#[inline]
+pub fn encode_repeated_message<M: Message, B: BufMut>(tag: u32, msgs: &[M], buf: &mut B) {
+    encoding::message::encode_repeated(tag, msgs, buf);
+}
+
+/// Merges one element of a repeated nested message field
+#[inline]
+pub fn merge_repeated_message<M: Message + Default, B: Buf>(
+    wire_type: WireType,
+    msgs: &mut Vec<M>,
+    buf: &mut B,
+    ctx: DecodeContext,
+) -> Result<(), DecodeError> {
+    encoding::message::merge_repeated(wire_type, msgs, buf, ctx)
+}
+
+/// Returns the encoded length of a repeated nested message field
+#[inline]
+pub fn encoded_len_repeated_message<M: Message>(tag: u32, msgs: &[M]) -> usize {
+    encoding::message::encoded_len_repeated(tag, msgs)
+}
+
+/// Writes the key and length prefix of a nested group of fields, e.g., an enum variant
+#[inline]
+pub fn encode_nested_header<B: BufMut>(tag: u32, len: usize, buf: &mut B) {
+    encoding::encode_key(tag, WireType::LengthDelimited, buf);
+    encoding::encode_varint(len as u64, buf);
+}
+
+/// Returns the encoded length of a nested group of fields with `len` bytes of content
+#[inline]
+pub fn encoded_len_nested(tag: u32, len: usize) -> usize {
+    encoding::key_len(tag) + encoding::encoded_len_varint(len as u64) + len
+}
+
+/// Merges a length-delimited group of fields by handing each decoded key to `merge_field`
+#[inline]
+pub fn merge_nested<B, F>(
+    wire_type: WireType,
+    buf: &mut B,
+    ctx: DecodeContext,
+    mut merge_field: F,
+) -> Result<(), DecodeError>
+where
+    B: Buf,
+    F: FnMut(u32, WireType, &mut B, DecodeContext) -> Result<(), DecodeError>,
+{
+    encoding::check_wire_type(WireType::LengthDelimited, wire_type)?;
+    encoding::merge_loop(&mut (), buf, ctx, |_, buf, ctx| {
+        let (tag, wire_type) = encoding::decode_key(buf)?;
+        merge_field(tag, wire_type, buf, ctx)
+    })
+}
+
+/// Skips a field that is unknown to the decoding type
+#[inline]
+pub fn skip_field<B: Buf>(
+    wire_type: WireType,
+    tag: u32,
+    buf: &mut B,
+    ctx: DecodeContext,
+) -> Result<(), DecodeError> {
+    encoding::skip_field(wire_type, tag, buf, ctx)
+}
@@ -1,5 +1,13 @@
 use crate::error::*;
 
+/// Field encoding used by `#[derive(BrittMarieData)]`
+#[doc(hidden)]
+pub mod field;
+/// Order-preserving key encoding
+mod ordered;
+
+pub use self::ordered::OrderedKey;
+
 // TODO: Fix this mess.
 // NOTE: Create common trait for BrittMarie data type and
 //       put prost as default behind a cfg flag. 
@@ -29,3 +37,17 @@ pub trait Key: prost::Message + Default + Clone + 'static {
     }
 }
 impl<T> Key for T where T: prost::Message + Default + Clone + 'static {}
+
+/// Returns the prefix shared by the RawStore records of `key` in indexes that
+/// store several records per key
+///
+/// The encoded key is prefixed with its length so that the prefix of one key is
+/// never a prefix of another one. Room is reserved for a sequence suffix.
+#[inline]
+pub(crate) fn key_prefix<K: Key>(key: &K) -> Result<Vec<u8>> {
+    let raw_key = key.into_raw()?;
+    let mut prefix = Vec::with_capacity(4 + raw_key.len() + 8);
+    prefix.extend_from_slice(&(raw_key.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(&raw_key);
+    Ok(prefix)
+}
@@ -0,0 +1,143 @@
+use crate::data::Key;
+use crate::error::*;
+
+/// A Key with an order-preserving binary encoding
+///
+/// Encoded keys compare bytewise in the same order as the keys themselves,
+/// which lets ordered indexes scan ranges of keys directly in the RawStore.
+pub trait OrderedKey: Key + Ord {
+    /// Appends the order-preserving encoding of the key to `buf`
+    fn encode_ordered(&self, buf: &mut Vec<u8>);
+    /// Decodes a key from its order-preserving encoding
+    fn decode_ordered(bytes: &[u8]) -> Result<Self>;
+
+    /// Returns the order-preserving encoding of the key
+    #[inline]
+    fn to_ordered(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_ordered(&mut buf);
+        buf
+    }
+}
+
+#[inline]
+fn fixed<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    if bytes.len() != N {
+        return Err(BrittMarieError::Serde(format!(
+            "expected {} key bytes, found {}",
+            N,
+            bytes.len()
+        )));
+    }
+    buf.copy_from_slice(bytes);
+    Ok(buf)
+}
+
+macro_rules! unsigned_key {
+    ($ty:ty) => {
+        impl OrderedKey for $ty {
+            #[inline]
+            fn encode_ordered(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_be_bytes());
+            }
+            #[inline]
+            fn decode_ordered(bytes: &[u8]) -> Result<Self> {
+                fixed(bytes).map(<$ty>::from_be_bytes)
+            }
+        }
+    };
+}
+
+// Signed integers flip the sign bit so that negative numbers sort first
+macro_rules! signed_key {
+    ($ty:ty, $unsigned:ty) => {
+        impl OrderedKey for $ty {
+            #[inline]
+            fn encode_ordered(&self, buf: &mut Vec<u8>) {
+                let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                buf.extend_from_slice(&flipped.to_be_bytes());
+            }
+            #[inline]
+            fn decode_ordered(bytes: &[u8]) -> Result<Self> {
+                let flipped = <$unsigned>::from_be_bytes(fixed(bytes)?);
+                Ok((flipped ^ (1 << (<$unsigned>::BITS - 1))) as $ty)
+            }
+        }
+    };
+}
+
+unsigned_key!(u32);
+unsigned_key!(u64);
+signed_key!(i32, u32);
+signed_key!(i64, u64);
+
+impl OrderedKey for bool {
+    #[inline]
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+    #[inline]
+    fn decode_ordered(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(BrittMarieError::Serde(String::from("invalid bool key"))),
+        }
+    }
+}
+
+// UTF-8 preserves the order of code points, so strings are stored as is
+impl OrderedKey for String {
+    #[inline]
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+    #[inline]
+    fn decode_ordered(bytes: &[u8]) -> Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| BrittMarieError::Serde(e.to_string()))
+    }
+}
+
+impl OrderedKey for Vec<u8> {
+    #[inline]
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+    #[inline]
+    fn decode_ordered(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ordered<K: OrderedKey + std::fmt::Debug>(mut keys: Vec<K>) {
+        keys.sort();
+        let encoded: Vec<Vec<u8>> = keys.iter().map(|k| k.to_ordered()).collect();
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(encoded, sorted);
+        for (key, bytes) in keys.iter().zip(encoded.iter()) {
+            assert_eq!(&K::decode_ordered(bytes).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn order_preserving_test() {
+        assert_ordered(vec![0u64, 1, 255, 256, u64::MAX, 1 << 40]);
+        assert_ordered(vec![i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_ordered(vec![i32::MIN, -1, 0, 7, i32::MAX]);
+        assert_ordered(vec![true, false]);
+        assert_ordered(vec![
+            String::from("b"),
+            String::from("a"),
+            String::from(""),
+            String::from("ab"),
+            String::from("ä"),
+        ]);
+        assert!(u64::decode_ordered(&[1, 2]).is_err());
+    }
+}
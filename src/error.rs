@@ -8,8 +8,16 @@ pub enum BrittMarieError {
     Insert(String),
     #[error("RawStore Read Error `{0}`")]
     Read(String),
+    #[error("RawStore Delete Error `{0}`")]
+    Delete(String),
     #[error("RawStore Checkpoint Error `{0}`")]
     Checkpoint(String),
+    #[error("Compression Error `{0}`")]
+    Compression(String),
+    #[error("Cipher Error `{0}`")]
+    Cipher(String),
+    #[error("No value persisted for `{0}`")]
+    NotFound(String),
     #[error("unknown data store error")]
     Unknown,
 }
@@ -0,0 +1,124 @@
+use super::Aggregator;
+
+/// Sum of the inputs
+pub struct Sum;
+
+impl Aggregator for Sum {
+    type Input = i64;
+    type Partial = i64;
+    type Output = i64;
+
+    #[inline]
+    fn lift(input: i64) -> i64 {
+        input
+    }
+    #[inline]
+    fn combine(partial: &mut i64, other: i64) {
+        *partial += other;
+    }
+    #[inline]
+    fn lower(partial: &i64) -> i64 {
+        *partial
+    }
+}
+
+/// Number of inputs
+pub struct Count;
+
+impl Aggregator for Count {
+    type Input = ();
+    type Partial = u64;
+    type Output = u64;
+
+    #[inline]
+    fn lift(_: ()) -> u64 {
+        1
+    }
+    #[inline]
+    fn combine(partial: &mut u64, other: u64) {
+        *partial += other;
+    }
+    #[inline]
+    fn lower(partial: &u64) -> u64 {
+        *partial
+    }
+}
+
+/// Smallest input
+pub struct Min;
+
+impl Aggregator for Min {
+    type Input = i64;
+    type Partial = i64;
+    type Output = i64;
+
+    #[inline]
+    fn lift(input: i64) -> i64 {
+        input
+    }
+    #[inline]
+    fn combine(partial: &mut i64, other: i64) {
+        *partial = i64::min(*partial, other);
+    }
+    #[inline]
+    fn lower(partial: &i64) -> i64 {
+        *partial
+    }
+}
+
+/// Largest input
+pub struct Max;
+
+impl Aggregator for Max {
+    type Input = i64;
+    type Partial = i64;
+    type Output = i64;
+
+    #[inline]
+    fn lift(input: i64) -> i64 {
+        input
+    }
+    #[inline]
+    fn combine(partial: &mut i64, other: i64) {
+        *partial = i64::max(*partial, other);
+    }
+    #[inline]
+    fn lower(partial: &i64) -> i64 {
+        *partial
+    }
+}
+
+/// Partial aggregate of [Avg]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AvgPartial {
+    #[prost(int64, tag = "1")]
+    pub sum: i64,
+    #[prost(uint64, tag = "2")]
+    pub count: u64,
+}
+
+/// Arithmetic mean of the inputs
+pub struct Avg;
+
+impl Aggregator for Avg {
+    type Input = i64;
+    type Partial = AvgPartial;
+    type Output = f64;
+
+    #[inline]
+    fn lift(input: i64) -> AvgPartial {
+        AvgPartial {
+            sum: input,
+            count: 1,
+        }
+    }
+    #[inline]
+    fn combine(partial: &mut AvgPartial, other: AvgPartial) {
+        partial.sum += other.sum;
+        partial.count += other.count;
+    }
+    #[inline]
+    fn lower(partial: &AvgPartial) -> f64 {
+        partial.sum as f64 / partial.count as f64
+    }
+}
@@ -0,0 +1,546 @@
+use crate::data::{key_prefix, Key, OrderedKey, Value};
+use crate::error::*;
+use crate::index::hash::DefaultHashBuilder;
+use crate::index::recency::Recency;
+use crate::index::{IndexOps, WriteMode};
+use crate::raw_store::RawStore;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Built-in aggregate functions
+mod functions;
+
+pub use self::functions::{Avg, AvgPartial, Count, Max, Min, Sum};
+
+/// Number of records fetched from the RawStore per scan
+const SCAN_BATCH_SIZE: usize = 1024;
+
+/// An encoded record key together with its encoded value
+type Record = (Vec<u8>, Vec<u8>);
+
+/// A mergeable aggregate function
+///
+/// Inputs are lifted into partial aggregates, which can be combined in any
+/// order and grouping, and a partial aggregate is lowered into the result.
+pub trait Aggregator {
+    type Input;
+    type Partial: Value;
+    type Output;
+
+    /// Turns a single input into a partial aggregate
+    fn lift(input: Self::Input) -> Self::Partial;
+    /// Merges `other` into `partial`
+    fn combine(partial: &mut Self::Partial, other: Self::Partial);
+    /// Turns a partial aggregate into the result
+    fn lower(partial: &Self::Partial) -> Self::Output;
+}
+
+/// In-memory partial aggregate of a key
+struct Entry<P> {
+    partial: P,
+    /// Whether the partial covers everything in the RawStore
+    complete: bool,
+    /// Sequence of the delta record that holds the partial while it is incomplete
+    seq: u64,
+    /// Delta records that are covered by a complete partial and can be deleted
+    absorbed: RefCell<Vec<u64>>,
+    /// Whether the partial has changes that are not in the RawStore
+    modified: Cell<bool>,
+}
+
+/// An Index of incrementally maintained aggregates per key
+///
+/// Updates to keys that are not in memory do not read the RawStore. They start a
+/// partial aggregate that is written as a delta record next to the stored
+/// aggregate of the key. Reads combine the stored aggregate, the deltas and the
+/// in-memory partial, and [AggregateIndex::compact] folds all deltas into the
+/// stored aggregates.
+pub struct AggregateIndex<K, A>
+where
+    K: Key + Eq + Hash,
+    A: Aggregator,
+{
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
+    /// In-memory partial aggregates
+    entries: HashMap<K, Entry<A::Partial>, DefaultHashBuilder>,
+    /// Order in which the keys in memory were last used
+    recency: Recency<K>,
+    /// Sequence of the next delta record, loaded from the RawStore on first use
+    next_seq: Option<u64>,
+    /// Max number of keys held in memory
+    capacity: usize,
+    /// Write Mode
+    mode: WriteMode,
+    /// The RawStore layer where things are persisted
+    raw_store: Rc<RefCell<RawStore>>,
+    _marker: PhantomData<A>,
+}
+
+impl<K, A> AggregateIndex<K, A>
+where
+    K: Key + Eq + Hash,
+    A: Aggregator,
+{
+    /// Creates an AggregateIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
+    #[inline]
+    pub fn new<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::default(), raw_store)
+    }
+
+    /// Creates an AggregateIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::Cow, raw_store)
+    }
+
+    fn setup<I>(
+        namespace: I,
+        capacity: usize,
+        mode: WriteMode,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> AggregateIndex<K, A>
+    where
+        I: Into<Vec<u8>>,
+    {
+        assert!(
+            capacity > 0,
+            "AggregateIndex needs room for at least one key"
+        );
+        AggregateIndex {
+            namespace: namespace.into(),
+            entries: HashMap::default(),
+            recency: Recency::new(),
+            next_seq: None,
+            capacity,
+            mode,
+            raw_store,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of keys held in memory
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Folds an input into the aggregate of a key
+    pub fn update(&mut self, key: K, input: A::Input) -> Result<()> {
+        let partial = A::lift(input);
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                A::combine(&mut entry.partial, partial);
+                entry.modified.set(true);
+            }
+            None => {
+                let seq = self.next_seq()?;
+                self.make_room()?;
+                let entry = Entry {
+                    partial,
+                    complete: false,
+                    seq,
+                    absorbed: RefCell::new(Vec::new()),
+                    modified: Cell::new(true),
+                };
+                self.entries.insert(key.clone(), entry);
+            }
+        }
+        self.recency.touch(&key);
+        if self.mode.is_cow() {
+            self.write(&key, &self.entries[&key])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the aggregate of a key, if it has any inputs
+    pub fn get(&mut self, key: &K) -> Result<Option<A::Output>> {
+        let complete = match self.entries.get(key) {
+            Some(entry) => entry.complete,
+            None => false,
+        };
+        if !complete && !self.complete(key)? {
+            return Ok(None);
+        }
+        self.recency.touch(key);
+        Ok(self.entries.get(key).map(|entry| A::lower(&entry.partial)))
+    }
+
+    /// Folds the delta records of all keys into their stored aggregates
+    pub fn compact(&mut self) -> Result<()> {
+        self.persist()?;
+        let mut from = Vec::new();
+        let mut group: Option<(Vec<u8>, Vec<Record>)> = None;
+        loop {
+            let records = self
+                .raw_store
+                .borrow()
+                .scan(&self.namespace, &from, SCAN_BATCH_SIZE)?;
+            let done = records.len() < SCAN_BATCH_SIZE;
+            for (record_key, raw_value) in records {
+                from = record_key.clone();
+                from.push(0);
+                let prefix = match split_record_key(&record_key)? {
+                    Some((prefix, _)) => prefix.to_vec(),
+                    // The sequence record
+                    None => continue,
+                };
+                match group.as_mut() {
+                    Some((group_prefix, members)) if *group_prefix == prefix => {
+                        members.push((record_key, raw_value))
+                    }
+                    _ => {
+                        if let Some((group_prefix, members)) = group.take() {
+                            self.compact_key(&group_prefix, members)?;
+                        }
+                        group = Some((prefix, vec![(record_key, raw_value)]));
+                    }
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        if let Some((prefix, members)) = group {
+            self.compact_key(&prefix, members)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the records of a single key as one stored aggregate
+    fn compact_key(&mut self, prefix: &[u8], records: Vec<Record>) -> Result<()> {
+        if records.len() < 2 {
+            // Nothing but the stored aggregate or a single delta
+            return Ok(());
+        }
+        let mut partial: Option<A::Partial> = None;
+        let mut batch = Vec::new();
+        for (record_key, raw_value) in records {
+            let value = <A::Partial as Value>::from_raw(&raw_value)?;
+            match partial.as_mut() {
+                Some(partial) => A::combine(partial, value),
+                None => partial = Some(value),
+            }
+            if record_key.len() > prefix.len() {
+                batch.push((record_key, None));
+            }
+        }
+        let partial = partial.expect("Unexpected error");
+        batch.push((prefix.to_vec(), Some(Value::into_raw(&partial)?)));
+        self.raw_store
+            .borrow_mut()
+            .write_bytes_batch(&self.namespace, batch)?;
+
+        // Everything the in-memory partial holds was persisted before compacting
+        let key = K::from_raw(&prefix[4..])?;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.partial = partial;
+            entry.complete = true;
+            entry.absorbed.borrow_mut().clear();
+        }
+        Ok(())
+    }
+
+    /// Combines the in-memory partial of a key with everything in the RawStore
+    ///
+    /// Returns false if the key has no inputs at all.
+    fn complete(&mut self, key: &K) -> Result<bool> {
+        let prefix = key_prefix(key)?;
+        let own_seq = self.entries.get(key).map(|entry| entry.seq);
+        let mut partial: Option<A::Partial> = None;
+        let mut absorbed = Vec::new();
+        let mut from = prefix.clone();
+        let mut done = false;
+        while !done {
+            let records = self
+                .raw_store
+                .borrow()
+                .scan(&self.namespace, &from, SCAN_BATCH_SIZE)?;
+            done = records.len() < SCAN_BATCH_SIZE;
+            for (record_key, raw_value) in records {
+                if !record_key.starts_with(&prefix) {
+                    done = true;
+                    break;
+                }
+                from = record_key.clone();
+                from.push(0);
+                if record_key.len() > prefix.len() {
+                    let seq = u64::decode_ordered(&record_key[prefix.len()..])?;
+                    absorbed.push(seq);
+                    // The in-memory partial is newer than its own delta
+                    if Some(seq) == own_seq {
+                        continue;
+                    }
+                }
+                let value = <A::Partial as Value>::from_raw(&raw_value)?;
+                match partial.as_mut() {
+                    Some(partial) => A::combine(partial, value),
+                    None => partial = Some(value),
+                }
+            }
+        }
+
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                if let Some(stored) = partial {
+                    A::combine(&mut entry.partial, stored);
+                }
+                entry.complete = true;
+                entry.modified.set(true);
+                entry.absorbed.borrow_mut().extend(absorbed);
+            }
+            None => {
+                let partial = match partial {
+                    Some(partial) => partial,
+                    None => return Ok(false),
+                };
+                let modified = !absorbed.is_empty();
+                self.make_room()?;
+                let entry = Entry {
+                    partial,
+                    complete: true,
+                    // Complete entries are never written as deltas
+                    seq: 0,
+                    absorbed: RefCell::new(absorbed),
+                    modified: Cell::new(modified),
+                };
+                self.entries.insert(key.clone(), entry);
+            }
+        }
+        if self.mode.is_cow() {
+            self.write(key, &self.entries[key])?;
+        }
+        Ok(true)
+    }
+
+    /// Hands out a sequence for a new delta record
+    fn next_seq(&mut self) -> Result<u64> {
+        let seq = match self.next_seq {
+            Some(seq) => seq,
+            None => match self.raw_store.borrow().get_bytes(&self.namespace, &[])? {
+                Some(raw) => u64::decode_ordered(&raw)?,
+                None => 0,
+            },
+        };
+        self.next_seq = Some(seq + 1);
+        Ok(seq)
+    }
+
+    /// Writes and drops the least recently used keys until there is room for one more
+    fn make_room(&mut self) -> Result<()> {
+        while self.entries.len() >= self.capacity {
+            let key = match self.recency.oldest() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            if let Some(entry) = self.entries.get(&key) {
+                self.write(&key, entry)?;
+            }
+            self.entries.remove(&key);
+            self.recency.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Writes the records of an entry to the RawStore if it has been modified
+    fn write(&self, key: &K, entry: &Entry<A::Partial>) -> Result<()> {
+        let mut records = Vec::new();
+        self.entry_records(key, entry, &mut records)?;
+        if !records.is_empty() {
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, records)?;
+            self.entry_written(entry);
+        }
+        Ok(())
+    }
+
+    /// Collects the records that bring the RawStore up to date with an entry
+    ///
+    /// A complete entry replaces the stored aggregate and the deltas it covers,
+    /// an incomplete one is written as its own delta record.
+    fn entry_records(
+        &self,
+        key: &K,
+        entry: &Entry<A::Partial>,
+        records: &mut Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    ) -> Result<()> {
+        if !entry.modified.get() {
+            return Ok(());
+        }
+        let prefix = key_prefix(key)?;
+        let raw_value = Value::into_raw(&entry.partial)?;
+        if entry.complete {
+            for seq in entry.absorbed.borrow().iter() {
+                records.push((delta_key(&prefix, *seq), None));
+            }
+            records.push((prefix, Some(raw_value)));
+        } else {
+            records.push((delta_key(&prefix, entry.seq), Some(raw_value)));
+            // Sequences must not be handed out again after a restart
+            if let Some(next_seq) = self.next_seq {
+                records.push((Vec::new(), Some(next_seq.to_ordered())));
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn entry_written(&self, entry: &Entry<A::Partial>) {
+        entry.modified.set(false);
+        entry.absorbed.borrow_mut().clear();
+    }
+}
+
+/// Returns the RawStore key of the delta record with sequence `seq`
+///
+/// The stored aggregate lives under the key prefix itself and the delta
+/// records under the prefix followed by their sequence.
+#[inline]
+fn delta_key(prefix: &[u8], seq: u64) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    seq.encode_ordered(&mut key);
+    key
+}
+
+/// Splits a record key into the key prefix and the delta sequence, if any
+///
+/// Returns `None` for the sequence record, which has an empty key.
+#[inline]
+fn split_record_key(record_key: &[u8]) -> Result<Option<(&[u8], Option<u64>)>> {
+    if record_key.is_empty() {
+        return Ok(None);
+    }
+    if record_key.len() < 4 {
+        return Err(BrittMarieError::Serde(String::from(
+            "aggregate record key too short",
+        )));
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&record_key[..4]);
+    let prefix_len = 4 + u32::from_be_bytes(len) as usize;
+    match record_key.len() - prefix_len {
+        0 => Ok(Some((record_key, None))),
+        8 => Ok(Some((
+            &record_key[..prefix_len],
+            Some(u64::decode_ordered(&record_key[prefix_len..])?),
+        ))),
+        _ => Err(BrittMarieError::Serde(String::from(
+            "invalid aggregate record key",
+        ))),
+    }
+}
+
+impl<K, A> IndexOps for AggregateIndex<K, A>
+where
+    K: Key + Eq + Hash,
+    A: Aggregator,
+{
+    fn persist(&self) -> Result<()> {
+        if self.mode.is_lazy() {
+            let mut records = Vec::new();
+            for (key, entry) in self.entries.iter() {
+                self.entry_records(key, entry, &mut records)?;
+            }
+            if records.is_empty() {
+                return Ok(());
+            }
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, records)?;
+            for entry in self.entries.values() {
+                self.entry_written(entry);
+            }
+        }
+        // Else just ignore as COW copies on each update
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn cold_update_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut sums: AggregateIndex<u64, Sum> = AggregateIndex::new("sums", 4, raw_store.clone());
+        // Spread partial aggregates over memory and several deltas per key
+        for round in 0..3 {
+            for key in 0..10 {
+                sums.update(key, (key * 10 + round) as i64).unwrap();
+            }
+        }
+        for key in 0..10 {
+            assert_eq!(sums.get(&key).unwrap(), Some((key * 30 + 3) as i64));
+        }
+        assert_eq!(sums.get(&100).unwrap(), None);
+        sums.update(3, 100).unwrap();
+        assert_eq!(sums.get(&3).unwrap(), Some(193));
+
+        sums.compact().unwrap();
+        for key in 0..10 {
+            sums.update(key, 1).unwrap();
+        }
+        sums.persist().unwrap();
+
+        // A fresh index continues the sequence of delta records
+        let mut other: AggregateIndex<u64, Sum> = AggregateIndex::new("sums", 2, raw_store);
+        for key in 0..10 {
+            other.update(key, 1).unwrap();
+        }
+        other.compact().unwrap();
+        assert_eq!(other.get(&3).unwrap(), Some(195));
+        assert_eq!(other.get(&9).unwrap(), Some(9 * 30 + 3 + 2));
+    }
+
+    #[test]
+    fn functions_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut counts: AggregateIndex<String, Count> =
+            AggregateIndex::cow("counts", 1, raw_store.clone());
+        let mut mins: AggregateIndex<String, Min> =
+            AggregateIndex::new("mins", 1, raw_store.clone());
+        let mut maxs: AggregateIndex<String, Max> =
+            AggregateIndex::new("maxs", 1, raw_store.clone());
+        let mut avgs: AggregateIndex<String, Avg> = AggregateIndex::new("avgs", 1, raw_store);
+        for (key, value) in [("a", 4), ("b", -2), ("a", -6), ("b", 8), ("a", 5)].iter() {
+            counts.update(key.to_string(), ()).unwrap();
+            mins.update(key.to_string(), *value).unwrap();
+            maxs.update(key.to_string(), *value).unwrap();
+            avgs.update(key.to_string(), *value).unwrap();
+        }
+        let a = String::from("a");
+        assert_eq!(counts.get(&a).unwrap(), Some(3));
+        assert_eq!(mins.get(&a).unwrap(), Some(-6));
+        assert_eq!(maxs.get(&a).unwrap(), Some(5));
+        assert_eq!(avgs.get(&a).unwrap(), Some(1.0));
+        assert_eq!(avgs.get(&String::from("b")).unwrap(), Some(3.0));
+    }
+}
@@ -0,0 +1,77 @@
+/// Arena size below which we never bother to compact
+const MIN_COMPACTION_SIZE: usize = 64 * 1024;
+
+/// Location of an encoded value within an [Arena]
+#[derive(Clone, Copy)]
+pub(crate) struct ArenaSlice {
+    offset: u32,
+    len: u32,
+}
+
+/// Append-only byte arena for encoded values
+///
+/// Slices are never freed individually. Instead the owner periodically
+/// compacts the arena by handing over every slice that is still alive.
+pub(crate) struct Arena {
+    buf: Vec<u8>,
+    /// Size of the arena right after the last compaction
+    compacted_len: usize,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena {
+            buf: Vec::new(),
+            compacted_len: 0,
+        }
+    }
+
+    /// Copies `bytes` into the arena
+    #[inline]
+    pub fn alloc(&mut self, bytes: &[u8]) -> ArenaSlice {
+        let offset = self.buf.len();
+        assert!(
+            offset + bytes.len() <= u32::MAX as usize,
+            "Arena is limited to 4GB of encoded values"
+        );
+        self.buf.extend_from_slice(bytes);
+        ArenaSlice {
+            offset: offset as u32,
+            len: bytes.len() as u32,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, slice: ArenaSlice) -> &[u8] {
+        let start = slice.offset as usize;
+        &self.buf[start..start + slice.len as usize]
+    }
+
+    /// Returns the number of bytes held by the arena
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns whether the arena has doubled in size since the last compaction
+    #[inline]
+    pub fn needs_compaction(&self) -> bool {
+        self.buf.len() >= MIN_COMPACTION_SIZE && self.buf.len() >= 2 * self.compacted_len
+    }
+
+    /// Moves all live slices into a fresh buffer and drops everything else
+    pub fn compact<'a, I>(&mut self, live: I)
+    where
+        I: Iterator<Item = &'a mut ArenaSlice>,
+    {
+        let mut buf = Vec::with_capacity(self.compacted_len);
+        for slice in live {
+            let bytes = self.get(*slice);
+            let offset = buf.len();
+            buf.extend_from_slice(bytes);
+            slice.offset = offset as u32;
+        }
+        self.compacted_len = buf.len();
+        self.buf = buf;
+    }
+}
@@ -0,0 +1,292 @@
+use super::slot::Slot;
+use super::HashIndex;
+use crate::data::{Key, Value};
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+
+/// A view into a single key of a [HashIndex], see [HashIndex::entry]
+pub enum Entry<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    /// Returns the value of the entry, inserting `default` if it is vacant
+    #[inline]
+    pub fn or_insert(self, default: V) -> ValueMut<'a, K, V, S> {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Returns the value of the entry, inserting the result of `default` if it is vacant
+    #[inline]
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> ValueMut<'a, K, V, S> {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Returns the value of the entry, inserting `V::default()` if it is vacant
+    #[inline]
+    pub fn or_default(self) -> ValueMut<'a, K, V, S> {
+        self.or_insert_with(V::default)
+    }
+
+    /// Runs `f` on the value of an occupied entry
+    #[inline]
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(&mut entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+
+    /// Returns the key of the entry
+    #[inline]
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+/// An entry whose key exists in the RawTable
+///
+/// Entries found in the RawStore are loaded into the RawTable first.
+pub struct OccupiedEntry<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    index: &'a mut HashIndex<K, V, S>,
+    bucket: usize,
+    hash: u64,
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    #[inline]
+    pub(crate) fn new(index: &'a mut HashIndex<K, V, S>, bucket: usize, hash: u64) -> Self {
+        OccupiedEntry {
+            index,
+            bucket,
+            hash,
+        }
+    }
+
+    #[inline]
+    pub fn key(&self) -> &K {
+        unsafe { &self.index.raw_table().bucket(self.bucket).as_ref().0 }
+    }
+
+    /// Returns the value without marking the entry as modified
+    #[inline]
+    pub fn get(&self) -> &V {
+        self.index.value_at(self.bucket)
+    }
+
+    /// Returns a mutable reference to the value
+    ///
+    /// In COW mode, the value is written once the reference is dropped.
+    #[inline]
+    pub fn get_mut(&mut self) -> ValueMut<'_, K, V, S> {
+        self.index
+            .touch(self.bucket, self.hash)
+            .expect("Unexpected error");
+        ValueMut::new(self.index, self.bucket)
+    }
+
+    /// Converts the entry into a mutable reference to its value, see [OccupiedEntry::get_mut]
+    #[inline]
+    pub fn into_mut(self) -> ValueMut<'a, K, V, S> {
+        self.index
+            .touch(self.bucket, self.hash)
+            .expect("Unexpected error");
+        ValueMut::new(self.index, self.bucket)
+    }
+
+    /// Replaces the value of the entry, returning the old one
+    #[inline]
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(&mut *self.get_mut(), value)
+    }
+
+    /// Removes the entry from the index, returning its value
+    #[inline]
+    pub fn remove(self) -> V {
+        self.index
+            .touch(self.bucket, self.hash)
+            .expect("Unexpected error");
+        let slot = self.index.slot_at(self.bucket);
+        let old = std::mem::replace(slot, Slot::Tombstone);
+        self.index
+            .write_through(self.bucket)
+            .expect("Unexpected error");
+        old.into_value(self.index.arena())
+            .expect("Unexpected error")
+            .expect("occupied entries hold a value")
+    }
+}
+
+/// An entry whose key is neither in the RawTable nor in the RawStore
+pub struct VacantEntry<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    index: &'a mut HashIndex<K, V, S>,
+    key: K,
+    hash: u64,
+    /// Bucket holding the tombstone of the key, if any
+    tombstone: Option<usize>,
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    #[inline]
+    pub(crate) fn new(
+        index: &'a mut HashIndex<K, V, S>,
+        key: K,
+        hash: u64,
+        tombstone: Option<usize>,
+    ) -> Self {
+        VacantEntry {
+            index,
+            key,
+            hash,
+            tombstone,
+        }
+    }
+
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    #[inline]
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts a value for the key and returns a mutable reference to it
+    ///
+    /// In COW mode, the value is written once the reference is dropped.
+    #[inline]
+    pub fn insert(self, value: V) -> ValueMut<'a, K, V, S> {
+        let slot = self.index.new_slot(value).expect("Unexpected error");
+        let bucket = match self.tombstone {
+            Some(bucket) => {
+                self.index
+                    .touch(bucket, self.hash)
+                    .expect("Unexpected error");
+                *self.index.slot_at(bucket) = slot;
+                bucket
+            }
+            None => {
+                let table = self.index.raw_table_mut();
+                // Make room before inserting, as in HashIndex::insert
+                if table.above_mod_threshold() {
+                    self.index.evict(self.hash).expect("Unexpected error");
+                }
+                unsafe {
+                    let bucket = table.insert(self.hash, (self.key, slot));
+                    table.bucket_index(&bucket)
+                }
+            }
+        };
+        ValueMut::new(self.index, bucket)
+    }
+}
+
+/// A mutable reference to the value of an entry
+///
+/// In COW mode, the value is written to the RawStore when the reference is
+/// dropped. If that write fails, the entry stays modified and is written on
+/// the next persist.
+pub struct ValueMut<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    index: &'a mut HashIndex<K, V, S>,
+    bucket: usize,
+}
+
+impl<'a, K, V, S> ValueMut<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    #[inline]
+    fn new(index: &'a mut HashIndex<K, V, S>, bucket: usize) -> Self {
+        ValueMut { index, bucket }
+    }
+}
+
+impl<'a, K, V, S> Deref for ValueMut<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    type Target = V;
+
+    #[inline]
+    fn deref(&self) -> &V {
+        self.index.value_at(self.bucket)
+    }
+}
+
+impl<'a, K, V, S> DerefMut for ValueMut<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut V {
+        self.index.value_at(self.bucket)
+    }
+}
+
+impl<'a, K, V, S> Drop for ValueMut<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    #[inline]
+    fn drop(&mut self) {
+        // A failed write leaves the bucket modified for the next persist
+        let _ = self.index.write_through(self.bucket);
+    }
+}
@@ -0,0 +1,108 @@
+/// Smallest number of bits a [BloomFilter] is created with
+const MIN_BITS: usize = 64;
+
+/// Bloom filter over the hashes of the keys that may exist in the RawStore
+///
+/// A miss is definite, while a hit may be a false positive at roughly the rate
+/// the filter was sized for. Keys cannot be removed, so the filter is rebuilt
+/// to drop deleted keys or to resize it once it holds more keys than expected.
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    /// Number of bits, always a power of two
+    num_bits: usize,
+    num_hashes: u32,
+    /// Number of keys the filter was sized for
+    expected_keys: usize,
+    fp_rate: f64,
+    /// Number of inserted keys, including duplicates
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Creates a filter that holds `expected_keys` at a false positive rate of about `fp_rate`
+    pub fn with_rate(expected_keys: usize, fp_rate: f64) -> Self {
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "false positive rate must be within (0, 1)"
+        );
+        let expected_keys = usize::max(expected_keys, 1);
+        let ln2 = std::f64::consts::LN_2;
+        let optimal_bits = -(expected_keys as f64) * fp_rate.ln() / (ln2 * ln2);
+        let num_bits = usize::max(optimal_bits.ceil() as usize, MIN_BITS).next_power_of_two();
+        let num_hashes = (num_bits as f64 / expected_keys as f64 * ln2).round() as u32;
+        BloomFilter {
+            bits: vec![0; num_bits / 64],
+            num_bits,
+            num_hashes: num_hashes.clamp(1, 16),
+            expected_keys,
+            fp_rate,
+            len: 0,
+        }
+    }
+
+    /// Yields the bit positions of a key hash
+    ///
+    /// Uses double hashing on a remixed hash, as weak hashers such as FxHash
+    /// leave little entropy in the low bits of small keys.
+    #[inline]
+    fn positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let hash = mix(hash);
+        let h1 = hash as u32 as usize;
+        let h2 = ((hash >> 32) | 1) as usize;
+        let mask = self.num_bits - 1;
+        (0..self.num_hashes as usize).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) & mask)
+    }
+
+    #[inline]
+    pub fn insert(&mut self, hash: u64) {
+        for pos in self.positions(hash) {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Returns false if the key has definitely not been inserted
+    #[inline]
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+
+    /// Returns the number of inserted keys, including duplicates
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether more keys have been inserted than the filter was sized for
+    #[inline]
+    pub fn is_saturated(&self) -> bool {
+        self.len > self.expected_keys
+    }
+
+    /// Creates an empty filter with the same false positive rate that fits `keys`
+    ///
+    /// The filter is never sized down, and leaves room for twice as many keys
+    /// so that a growing key space is rebuilt a logarithmic number of times.
+    #[inline]
+    pub fn resized(&self, keys: usize) -> Self {
+        let expected_keys = usize::max(self.expected_keys, keys.saturating_mul(2));
+        BloomFilter::with_rate(expected_keys, self.fp_rate)
+    }
+
+    /// Returns the number of bytes held by the filter
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+}
+
+/// Finalizer of MurmurHash3
+#[inline(always)]
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
@@ -0,0 +1,180 @@
+use super::slot::Slot;
+use super::table::RawIter;
+use super::{make_hash, HashIndex};
+use crate::data::{Key, Value};
+use crate::error::*;
+use crate::raw_store::scan::RawScan;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Bound;
+
+/// Number of records fetched from the RawStore at a time
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// Iterator over every live entry of a [HashIndex]
+///
+/// Entries in the RawTable are yielded first, followed by the ones that only
+/// exist in the RawStore. Keys that are in memory are skipped in the RawStore,
+/// so each key is yielded once with its latest value.
+pub struct Iter<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    index: &'a HashIndex<K, V, S>,
+    memory: RawIter<(K, Slot<V>)>,
+    disk: RawScan,
+}
+
+impl<'a, K, V, S> Iter<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    pub(crate) fn new(index: &'a HashIndex<K, V, S>) -> Self {
+        Iter {
+            index,
+            // The index is borrowed mutably for the lifetime of the iterator,
+            // so the RawTable cannot be resized underneath it.
+            memory: unsafe { index.raw_table().iter() },
+            disk: RawScan::new(
+                index.raw_store.clone(),
+                index.namespace.clone(),
+                Vec::new(),
+                Bound::Unbounded,
+                SCAN_BATCH_SIZE,
+            ),
+        }
+    }
+
+    /// Returns the next entry, or the error that occurred while decoding or scanning it
+    pub(crate) fn try_next(&mut self) -> Result<Option<(K, V)>> {
+        match self.next_memory()? {
+            Some(entry) => Ok(Some(entry)),
+            None => self.next_disk(),
+        }
+    }
+
+    fn next_memory(&mut self) -> Result<Option<(K, V)>> {
+        for bucket in &mut self.memory {
+            let (key, slot) = unsafe { bucket.as_ref() };
+            if slot.is_tombstone() {
+                continue;
+            }
+            let value = slot.to_value(self.index.arena())?;
+            return Ok(Some((key.clone(), value)));
+        }
+        Ok(None)
+    }
+
+    fn next_disk(&mut self) -> Result<Option<(K, V)>> {
+        while let Some(record) = self.disk.next() {
+            let (key, raw) = record?;
+            let key = K::from_raw(&key)?;
+            if self.in_memory(&key) {
+                continue;
+            }
+            return Ok(Some((key, V::from_raw(&raw)?)));
+        }
+        Ok(None)
+    }
+
+    /// Whether the RawTable holds a newer version of the key, or its tombstone
+    ///
+    /// The lookup does not count as an access, so iterating leaves the
+    /// eviction order as it is.
+    #[inline]
+    fn in_memory(&self, key: &K) -> bool {
+        let hash = make_hash(&self.index.hash_builder, key);
+        self.index
+            .raw_table()
+            .peek(hash, |x| key.eq(&x.0))
+            .is_some()
+    }
+}
+
+impl<'a, K, V, S> Iterator for Iter<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().expect("Unexpected error")
+    }
+}
+
+/// Same as [Iter] but yields errors instead of panicking on them
+pub struct TryIter<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    pub(crate) inner: Iter<'a, K, V, S>,
+}
+
+impl<'a, K, V, S> Iterator for TryIter<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    type Item = Result<(K, V)>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.try_next().transpose()
+    }
+}
+
+/// Iterator over the keys of a [HashIndex], see [Iter]
+pub struct Keys<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    pub(crate) inner: Iter<'a, K, V, S>,
+}
+
+impl<'a, K, V, S> Iterator for Keys<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+}
+
+/// Iterator over the values of a [HashIndex], see [Iter]
+pub struct Values<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    pub(crate) inner: Iter<'a, K, V, S>,
+}
+
+impl<'a, K, V, S> Iterator for Values<'a, K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    type Item = V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+}
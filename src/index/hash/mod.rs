@@ -5,12 +5,13 @@
 // SPDX-License-Identifier: MIT
 
 use std::borrow::Borrow;
+use std::cmp::Reverse;
 use std::hash::{BuildHasher, Hash, Hasher};
 
 use crate::data::{Key, Value};
 use crate::error::*;
-use crate::hint::unlikely;
-use crate::index::{HashOps, IndexOps, WriteMode};
+use crate::hint::{likely, unlikely};
+use crate::index::{DecodeMode, EvictionPolicy, HashOps, IndexOps, ResizePolicy, WriteMode};
 
 cfg_if::cfg_if! {
     // Use the SSE2 implementation if possible: it allows us to scan 16 buckets
@@ -35,32 +36,118 @@ cfg_if::cfg_if! {
     }
 }
 
+mod arena;
 mod bitmask;
+mod entry;
+mod filter;
+mod iter;
+mod slot;
 mod table;
 
+use self::arena::Arena;
+pub use self::entry::{Entry, OccupiedEntry, VacantEntry, ValueMut};
+use self::filter::BloomFilter;
+pub use self::iter::{Iter, Keys, TryIter, Values};
+use self::slot::Slot;
 use self::table::RawTable;
+#[cfg(feature = "compression")]
+use crate::raw_store::compression::Compression;
+use crate::raw_store::memory::MemoryShare;
+use crate::raw_store::scan::successor;
 use crate::raw_store::RawStore;
-use std::cell::{RefCell, UnsafeCell};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::rc::Rc;
 
 // Set FxHash to default as most keys tend to be small
 pub type DefaultHashBuilder = fxhash::FxBuildHasher;
 
-pub struct HashIndex<K, V>
+/// Randomly seeded SipHash
+///
+/// Slower than [DefaultHashBuilder], but resistant to HashDoS attacks by
+/// keys from untrusted sources.
+pub type RandomHashBuilder = std::collections::hash_map::RandomState;
+
+/// Capacity below which a RawTable is never shrunk to meet the memory budget
+const MIN_SHRINK_CAPACITY: usize = 16;
+
+/// Number of keys read from the RawStore at a time when rebuilding a BloomFilter
+const FILTER_SCAN_BATCH_SIZE: usize = 1024;
+
+/// RawStore namespace holding the hot keys of each HashIndex, keyed by its namespace
+const HOT_KEYS_NAMESPACE: &[u8] = b"_britt_marie_hot_keys";
+
+pub struct HashIndex<K, V, S = DefaultHashBuilder>
 where
     K: Key,
     V: Value,
 {
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
     /// Hasher for the keys
-    hash_builder: fxhash::FxBuildHasher,
+    hash_builder: S,
     /// In-memory RawTable
-    raw_table: UnsafeCell<RawTable<(K, V)>>,
+    raw_table: UnsafeCell<RawTable<(K, Slot<V>)>>,
     /// Write Mode
     mode: WriteMode,
+    /// Decode Mode for values fetched from the RawStore
+    decode_mode: DecodeMode,
+    /// Whether the RawTable may grow beyond its initial capacity
+    resize_policy: ResizePolicy,
+    /// Whether values are kept encoded in the arena
+    compact: bool,
+    /// Bytes that the RawTable and the arena may take up in compact mode
+    compact_bytes: usize,
+    /// Storage for encoded values in compact mode
+    arena: UnsafeCell<Arena>,
+    /// Buckets that have been decoded by reads since the last repack
+    decoded: UnsafeCell<Vec<usize>>,
+    /// Filter over the keys that may exist in the RawStore, if enabled
+    filter: UnsafeCell<Option<BloomFilter>>,
+    /// Number of hottest keys recorded on persist, see [HashIndex::with_hot_keys]
+    hot_keys: usize,
+    /// Lookups served by the RawTable
+    hits: Cell<u64>,
+    /// Lookups that had to go to the RawStore
+    misses: Cell<u64>,
+    /// Memory accounting, if the RawStore has a memory budget
+    memory: Option<Rc<MemoryShare>>,
+    /// Capacity the RawTable was created with
+    initial_capacity: usize,
     /// The RawStore layer where things are persisted
     raw_store: Rc<RefCell<RawStore>>,
 }
 
+/// Encodes a list of encoded keys, each prefixed with its length
+fn encode_key_list(keys: &[Vec<u8>]) -> Vec<u8> {
+    let len = keys.iter().map(|key| 4 + key.len()).sum();
+    let mut bytes = Vec::with_capacity(len);
+    for key in keys {
+        bytes.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(key);
+    }
+    bytes
+}
+
+/// Reverts [encode_key_list]
+fn decode_key_list(mut bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let corrupt = || BrittMarieError::Serde(String::from("corrupt hot key list"));
+    let mut keys = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 4 {
+            return Err(corrupt());
+        }
+        let (len, rest) = bytes.split_at(4);
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        if rest.len() < len {
+            return Err(corrupt());
+        }
+        let (key, rest) = rest.split_at(len);
+        keys.push(key.to_vec());
+        bytes = rest;
+    }
+    Ok(keys)
+}
+
 #[inline]
 pub(crate) fn make_hash<K: Hash + ?Sized>(hash_builder: &impl BuildHasher, val: &K) -> u64 {
     let mut state = hash_builder.build_hasher();
@@ -74,123 +161,907 @@ where
     V: Value,
 {
     /// Creates a HashIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
     #[inline]
-    pub fn new(capacity: usize, mod_factor: f32, raw_store: Rc<RefCell<RawStore>>) -> Self {
-        Self::setup(capacity, mod_factor, WriteMode::default(), raw_store)
+    pub fn new<I>(
+        namespace: I,
+        capacity: usize,
+        mod_factor: f32,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, mod_factor, WriteMode::default(), raw_store)
     }
 
     /// Creates a ValueIndex with Copy-On-Write enabled
     #[inline]
-    pub fn cow(capacity: usize, mod_factor: f32, raw_store: Rc<RefCell<RawStore>>) -> Self {
-        Self::setup(capacity, mod_factor, WriteMode::Cow, raw_store)
+    pub fn cow<I>(
+        namespace: I,
+        capacity: usize,
+        mod_factor: f32,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, mod_factor, WriteMode::Cow, raw_store)
     }
 
-    fn setup(
+    fn setup<I>(
+        namespace: I,
         capacity: usize,
         mod_factor: f32,
         mode: WriteMode,
         raw_store: Rc<RefCell<RawStore>>,
-    ) -> HashIndex<K, V> {
-        HashIndex {
+    ) -> HashIndex<K, V>
+    where
+        I: Into<Vec<u8>>,
+    {
+        let mut index = HashIndex {
+            namespace: namespace.into(),
             hash_builder: DefaultHashBuilder::default(),
             raw_table: UnsafeCell::new(RawTable::with_capacity(capacity, mod_factor)),
             mode,
+            decode_mode: DecodeMode::default(),
+            resize_policy: ResizePolicy::default(),
+            compact: false,
+            compact_bytes: 0,
+            arena: UnsafeCell::new(Arena::new()),
+            decoded: UnsafeCell::new(Vec::new()),
+            filter: UnsafeCell::new(None),
+            hot_keys: 0,
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            memory: None,
+            initial_capacity: capacity,
             raw_store,
+        };
+        index.memory = index
+            .raw_store
+            .borrow_mut()
+            .register_memory(&index.namespace, index.memory_size());
+        index
+    }
+}
+
+impl<K, V, S> HashIndex<K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    /// Replaces the hasher of the keys, e.g., with a [RandomHashBuilder]
+    ///
+    /// Must be called before the first insert, as entries are placed by their hash.
+    ///
+    /// ```
+    /// # use britt_marie::{HashIndex, RandomHashBuilder, RawStore};
+    /// # use std::{cell::RefCell, rc::Rc};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let raw_store = Rc::new(RefCell::new(RawStore::new(dir.path().to_str().unwrap())));
+    /// let sessions: HashIndex<String, u64, RandomHashBuilder> =
+    ///     HashIndex::new("_sessions", 128, 0.6, raw_store).with_hasher(RandomHashBuilder::new());
+    /// ```
+    #[inline]
+    pub fn with_hasher<T>(self, hash_builder: T) -> HashIndex<K, V, T>
+    where
+        T: BuildHasher,
+    {
+        assert!(self.is_empty(), "hasher must be set before inserting");
+        let mut index = HashIndex {
+            namespace: self.namespace,
+            hash_builder,
+            raw_table: self.raw_table,
+            mode: self.mode,
+            decode_mode: self.decode_mode,
+            resize_policy: self.resize_policy,
+            compact: self.compact,
+            compact_bytes: self.compact_bytes,
+            arena: self.arena,
+            decoded: self.decoded,
+            filter: self.filter,
+            hot_keys: self.hot_keys,
+            hits: self.hits,
+            misses: self.misses,
+            memory: self.memory,
+            initial_capacity: self.initial_capacity,
+            raw_store: self.raw_store,
+        };
+        // Keys in the filter are placed by their hash as well
+        index.rebuild_filter().expect("Unexpected error");
+        index
+    }
+
+    /// Sets the [DecodeMode] used for values fetched from the RawStore
+    ///
+    /// ```
+    /// # use britt_marie::{DecodeMode, HashIndex, RawStore};
+    /// # use std::{cell::RefCell, rc::Rc};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let raw_store = Rc::new(RefCell::new(RawStore::new(dir.path().to_str().unwrap())));
+    /// let profiles: HashIndex<u64, String> =
+    ///     HashIndex::new("_profiles", 128, 0.6, raw_store).with_decode_mode(DecodeMode::Lazy);
+    /// ```
+    #[inline]
+    pub fn with_decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    /// Sets the [ResizePolicy] of the RawTable
+    #[inline]
+    pub fn with_resize_policy(mut self, resize_policy: ResizePolicy) -> Self {
+        self.resize_policy = resize_policy;
+        self
+    }
+
+    /// Sets the [EvictionPolicy] of the RawTable
+    #[inline]
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.raw_table
+            .get_mut()
+            .set_eviction_policy(eviction_policy);
+        self
+    }
+
+    /// Compresses the values of the index in the RawStore
+    ///
+    /// Panics if the namespace of the index already holds uncompressed values, see
+    /// [RawStore::set_compression].
+    #[cfg(feature = "compression")]
+    #[inline]
+    pub fn with_compression(self, compression: Compression) -> Self {
+        self.raw_store
+            .borrow_mut()
+            .set_compression(self.namespace.clone(), compression)
+            .expect("Unexpected error");
+        self
+    }
+
+    /// Keeps values encoded in memory and decodes them on access
+    ///
+    /// Values with heap allocated fields (e.g., `Vec` or `String`) take up a fraction
+    /// of their decoded size. Instead of a number of entries, the index is then held
+    /// to `max_bytes` of RawTable and encoded values: a full RawTable grows as long
+    /// as both fit, and entries are dropped once the encoded values outgrow it. Reads
+    /// decode a copy of the value that is packed again on the next update of the index.
+    #[inline]
+    pub fn with_compact_values(mut self, max_bytes: usize) -> Self {
+        self.compact = true;
+        self.compact_bytes = max_bytes;
+        self
+    }
+
+    /// Keeps a bloom filter over the keys in the RawStore to skip lookups of new keys
+    ///
+    /// The filter is sized for `expected_keys` at a false positive rate of about
+    /// `fp_rate` and is built from the keys that are already in the RawStore, e.g.,
+    /// after a restore. Misses on the RawTable only reach the RawStore if the filter
+    /// may contain the key. Once more keys have been written than it was sized for,
+    /// the filter is rebuilt with room for twice as many on the next update.
+    ///
+    /// ```
+    /// # use britt_marie::{HashIndex, RawStore};
+    /// # use std::{cell::RefCell, rc::Rc};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let raw_store = Rc::new(RefCell::new(RawStore::new(dir.path().to_str().unwrap())));
+    /// let sessions: HashIndex<u64, u64> =
+    ///     HashIndex::new("_sessions", 128, 0.6, raw_store).with_bloom_filter(1 << 20, 0.01);
+    /// ```
+    #[inline]
+    pub fn with_bloom_filter(mut self, expected_keys: usize, fp_rate: f64) -> Self {
+        *self.filter.get_mut() = Some(BloomFilter::with_rate(expected_keys, fp_rate));
+        self.rebuild_filter().expect("Unexpected error");
+        self
+    }
+
+    /// Records up to `limit` of the hottest keys in the RawTable on every persist
+    ///
+    /// After a restore, [HashIndex::warm_up] loads them back into the RawTable.
+    /// Keys are ranked by the [EvictionPolicy], where `Lru` and `Lfu` keep the most
+    /// detail, while the other policies only tell touched keys from the rest.
+    #[inline]
+    pub fn with_hot_keys(mut self, limit: usize) -> Self {
+        self.hot_keys = limit;
+        self
+    }
+
+    /// Preloads the hot keys recorded at the last persist into the RawTable
+    ///
+    /// Returns the number of loaded entries, see [HashIndex::warm_up_keys].
+    ///
+    /// ```
+    /// # use britt_marie::{HashIndex, RawStore};
+    /// # use std::{cell::RefCell, rc::Rc};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let raw_store = Rc::new(RefCell::new(RawStore::new(dir.path().to_str().unwrap())));
+    /// let mut counters: HashIndex<u64, u64> =
+    ///     HashIndex::new("_counters", 128, 0.6, raw_store).with_hot_keys(128);
+    /// let loaded = counters.warm_up().unwrap();
+    /// ```
+    pub fn warm_up(&mut self) -> Result<usize> {
+        let bytes = self
+            .raw_store
+            .borrow_mut()
+            .get_bytes(HOT_KEYS_NAMESPACE, &self.namespace)?;
+        let keys = match bytes {
+            Some(bytes) => decode_key_list(&bytes)?,
+            None => return Ok(0),
+        };
+        let keys = keys
+            .iter()
+            .map(|key| K::from_raw(key))
+            .collect::<Result<Vec<K>>>()?;
+        self.warm_up_keys(keys)
+    }
+
+    /// Preloads the given keys from the RawStore into the RawTable
+    ///
+    /// Entries are loaded as in sync with the RawStore, so they are dropped rather
+    /// than written back if they are not modified. Keys that are already in the
+    /// RawTable or not in the RawStore are skipped, and loading stops once the
+    /// RawTable is full so that no entry is dropped to make room.
+    ///
+    /// Returns the number of loaded entries.
+    pub fn warm_up_keys<I>(&mut self, keys: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = K>,
+    {
+        self.repack();
+        let mut loaded = 0;
+        for key in keys {
+            self.grow_if_full();
+            if self.raw_table().is_full() {
+                break;
+            }
+            let hash = make_hash(&self.hash_builder, &key);
+            if self.raw_table().find(hash, |x| key.eq(&x.0)).is_some() {
+                continue;
+            }
+            let raw = match self.raw_store_get_raw(&key)? {
+                Some(raw) => raw,
+                None => continue,
+            };
+            if self.decode_mode.is_lazy() || self.compact {
+                self.insert_raw(key, raw);
+            } else {
+                self.insert_safe_slot(key, Slot::Value(V::from_raw(&raw)?));
+            }
+            loaded += 1;
         }
+        self.sync_memory()?;
+        Ok(loaded)
+    }
+
+    /// Rebuilds the bloom filter from the keys in the RawStore
+    ///
+    /// Drops keys that have been removed since the filter was built. Does nothing
+    /// if the index has no filter, see [HashIndex::with_bloom_filter].
+    pub fn rebuild_filter(&mut self) -> Result<()> {
+        // Every key written by the index went into the current filter, so the
+        // number of inserted keys bounds the number of keys in the RawStore
+        let mut filter = match self.filter.get_mut() {
+            Some(filter) => filter.resized(filter.len()),
+            None => return Ok(()),
+        };
+        self.fill_filter(&mut filter)?;
+        // Keys the index has not seen, e.g., after a restore, may not fit
+        if filter.is_saturated() {
+            filter = filter.resized(filter.len());
+            self.fill_filter(&mut filter)?;
+        }
+        *self.filter.get_mut() = Some(filter);
+        Ok(())
+    }
+
+    /// Inserts the keys in the RawStore into a filter
+    fn fill_filter(&self, filter: &mut BloomFilter) -> Result<()> {
+        let raw_store = self.raw_store.borrow_mut();
+        let mut from = Vec::new();
+        loop {
+            let keys = raw_store.scan_keys(&self.namespace, &from, FILTER_SCAN_BATCH_SIZE)?;
+            for key in keys.iter() {
+                filter.insert(make_hash(&self.hash_builder, &K::from_raw(key)?));
+            }
+            if keys.len() < FILTER_SCAN_BATCH_SIZE {
+                return Ok(());
+            }
+            if let Some(last) = keys.into_iter().last() {
+                from = successor(last);
+            }
+        }
+    }
+
+    /// Rebuilds the bloom filter once it holds more keys than it was sized for
+    #[inline]
+    fn grow_filter_if_saturated(&mut self) -> Result<()> {
+        let saturated = matches!(self.filter.get_mut(), Some(filter) if filter.is_saturated());
+        if unlikely(saturated) {
+            self.rebuild_filter()?;
+        }
+        Ok(())
     }
 
     /// Internal helper function to access a RawTable
     #[inline(always)]
-    fn raw_table(&self) -> &RawTable<(K, V)> {
+    fn raw_table(&self) -> &RawTable<(K, Slot<V>)> {
         unsafe { &*self.raw_table.get() }
     }
 
     /// Internal helper function to access a mutable RawTable
     #[inline(always)]
-    fn raw_table_mut(&self) -> &mut RawTable<(K, V)> {
+    fn raw_table_mut(&self) -> &mut RawTable<(K, Slot<V>)> {
         unsafe { &mut *self.raw_table.get() }
     }
 
+    /// Internal helper function to access the BloomFilter, if any
+    #[inline(always)]
+    fn filter_mut(&self) -> Option<&mut BloomFilter> {
+        unsafe { (*self.filter.get()).as_mut() }
+    }
+
+    /// Internal helper function to access the Arena
+    #[inline(always)]
+    fn arena(&self) -> &Arena {
+        unsafe { &*self.arena.get() }
+    }
+
+    /// Internal helper function to access a mutable Arena
+    #[inline(always)]
+    fn arena_mut(&self) -> &mut Arena {
+        unsafe { &mut *self.arena.get() }
+    }
+
+    /// Creates a Slot for a new value based on the storage mode
+    #[inline]
+    fn new_slot(&self, v: V) -> Result<Slot<V>> {
+        let mut slot = Slot::Value(v);
+        if self.compact {
+            slot.pack(self.arena_mut())?;
+        }
+        Ok(slot)
+    }
+
+    /// Packs values that were decoded by reads back into the Arena
+    ///
+    /// Takes `&mut self` as no references to decoded values may be alive.
+    #[inline]
+    fn repack(&mut self) {
+        if !self.compact {
+            return;
+        }
+        let decoded = std::mem::take(self.decoded.get_mut());
+        let table = self.raw_table_mut();
+        let arena = self.arena_mut();
+        for index in decoded {
+            unsafe {
+                // The bucket may have been erased or reused since the read
+                if table.is_bucket_full(index) {
+                    // TODO: handle err?
+                    let _ = table.bucket(index).as_mut().1.pack(arena);
+                }
+            }
+        }
+        if arena.needs_compaction() {
+            self.compact_arena();
+        }
+    }
+
+    /// Drops the encoded values of the arena that no bucket refers to anymore
+    fn compact_arena(&mut self) {
+        let table = self.raw_table.get_mut();
+        let arena = self.arena.get_mut();
+        unsafe {
+            let live = table.iter().filter_map(|bucket| match &mut bucket.as_mut().1 {
+                Slot::Packed(slice) => Some(slice),
+                _ => None,
+            });
+            arena.compact(live);
+        }
+    }
+
+    /// Grows a full RawTable according to the [ResizePolicy]
+    ///
+    /// Growing moves all entries, so it only happens in operations that take
+    /// `&mut self`, after the remembered bucket indexes have been used up.
+    ///
+    /// In compact mode, the table grows as long as it fits in the byte budget
+    /// together with the encoded values.
+    #[inline]
+    fn grow_if_full(&mut self) {
+        let (max_bytes, values_size) = match self.resize_policy {
+            _ if self.compact => (self.compact_bytes, self.arena().size()),
+            ResizePolicy::Grow { max_bytes } => (max_bytes, 0),
+            // Tables that were shrunk to meet the memory budget may grow back
+            ResizePolicy::Fixed if self.memory.is_some() => (
+                RawTable::<(K, Slot<V>)>::allocation_size(self.initial_capacity).unwrap_or(0),
+                0,
+            ),
+            ResizePolicy::Fixed => return,
+        };
+        if likely(!self.raw_table().is_full()) {
+            return;
+        }
+        debug_assert!(self.decoded.get_mut().is_empty());
+        let capacity = usize::max(self.capacity() * 2, 1);
+        match RawTable::<(K, Slot<V>)>::allocation_size(capacity) {
+            Some(size) if size + values_size <= max_bytes && self.memory_allows(size) => {
+                let hash_builder = &self.hash_builder;
+                self.raw_table
+                    .get_mut()
+                    .resize(capacity, |x| make_hash(hash_builder, &x.0));
+            }
+            // Past the budget, full tables drop entries as in ResizePolicy::Fixed
+            _ => (),
+        }
+    }
+
+    /// Returns the estimated memory held by the index
+    #[inline]
+    fn memory_size(&self) -> usize {
+        let table_size = RawTable::<(K, Slot<V>)>::allocation_size(self.capacity()).unwrap_or(0);
+        let filter_size = unsafe { (*self.filter.get()).as_ref().map_or(0, BloomFilter::size) };
+        table_size + self.arena().size() + filter_size
+    }
+
+    /// Whether the memory budget leaves room for a RawTable of `table_size` bytes
+    ///
+    /// If not, the demand is reported anyway so that colder indexes are asked to
+    /// shrink, leaving room for a later attempt.
+    fn memory_allows(&self, table_size: usize) -> bool {
+        let share = match &self.memory {
+            Some(share) => share,
+            None => return true,
+        };
+        let size = table_size + self.arena().size();
+        let mut raw_store = self.raw_store.borrow_mut();
+        let fits = match raw_store.memory_manager() {
+            Some(memory) => memory.used() - share.bytes() + size <= memory.budget(),
+            None => true,
+        };
+        if !fits {
+            share.set_bytes(size);
+            raw_store.rebalance_memory();
+        }
+        fits
+    }
+
+    /// Applies the memory budget of the RawStore, if any
+    ///
+    /// Shrinks the RawTable if the [MemoryManager](crate::MemoryManager) asked for
+    /// it and reports the estimated memory usage, which may in turn ask colder
+    /// indexes to shrink, see [IndexOps::enforce_budget].
+    fn sync_memory(&mut self) -> Result<()> {
+        let share = match &self.memory {
+            Some(share) => share.clone(),
+            None => return Ok(()),
+        };
+        self.enforce_budget()?;
+        share.set_bytes(self.memory_size());
+        self.raw_store.borrow_mut().rebalance_memory();
+        Ok(())
+    }
+
+    /// Drops entries until the RawTable and the encoded values fit in the byte
+    /// budget of compact mode
+    fn fit_compact_bytes(&mut self) -> Result<()> {
+        if !self.compact {
+            return Ok(());
+        }
+        let table_size = |index: &Self| {
+            RawTable::<(K, Slot<V>)>::allocation_size(index.capacity()).unwrap_or(usize::MAX)
+        };
+        if table_size(self) + self.arena().size() <= self.compact_bytes {
+            return Ok(());
+        }
+        // Values that have been replaced may still take up room
+        self.compact_arena();
+        while table_size(self) + self.arena().size() > self.compact_bytes
+            && self.capacity() > MIN_SHRINK_CAPACITY
+        {
+            let capacity = self.capacity() / 2;
+            self.shrink_capacity(capacity)?;
+        }
+        Ok(())
+    }
+
+    /// Shrinks the RawTable until the estimated memory usage fits in `target` bytes
+    ///
+    /// Modified entries are written to the RawStore first, after which entries
+    /// that no longer fit are dropped as picked by the [EvictionPolicy].
+    fn shrink_to(&mut self, target: usize) -> Result<()> {
+        let arena_size = self.arena().size();
+        let current = self.capacity();
+        let mut capacity = current;
+        while capacity > MIN_SHRINK_CAPACITY
+            && RawTable::<(K, Slot<V>)>::allocation_size(capacity).unwrap_or(usize::MAX)
+                + arena_size
+                > target
+        {
+            capacity /= 2;
+        }
+        if capacity >= current {
+            return Ok(());
+        }
+        self.shrink_capacity(capacity)
+    }
+
+    /// Shrinks the RawTable to `capacity`, see [HashIndex::shrink_to]
+    fn shrink_capacity(&mut self, capacity: usize) -> Result<()> {
+        self.persist_modified()?;
+        let hash_builder = &self.hash_builder;
+        let table = self.raw_table.get_mut();
+        table.shrink(capacity, |x| make_hash(hash_builder, &x.0));
+        if self.compact {
+            self.compact_arena();
+        }
+        Ok(())
+    }
+
     /// Insert a Key-Value record into the RawTable
     ///
     /// The function will evict a bucket if the table is above the given
-    /// modification threshold.
+    /// modification threshold. Returns the index of the bucket.
+    #[inline]
+    fn insert(&self, k: K, v: V) -> Result<usize> {
+        self.insert_slot(k, self.new_slot(v)?)
+    }
+
+    /// Insert a Slot into the RawTable, see [HashIndex::insert]
     #[inline]
-    fn insert(&self, k: K, v: V) -> Option<V> {
+    fn insert_slot(&self, k: K, slot: Slot<V>) -> Result<usize> {
         let hash = make_hash(&self.hash_builder, &k);
         let table = self.raw_table_mut();
         unsafe {
             // If the entry is already in the RawTable then
             // replace it with new one. Otherwise, insert the
             // new entry.
-            if let Some(item) = table.find_mut(hash, |x| k.eq(&x.0)) {
-                Some(std::mem::replace(&mut item.as_mut().1, v))
+            let bucket = if let Some(item) = table.find_mut(hash, |x| k.eq(&x.0)) {
+                item.as_mut().1 = slot;
+                item
             } else {
                 // If we are above the modification threshold, then
                 // move a modified entry to the RawStore.
                 if table.above_mod_threshold() {
-                    let bucket = table.evict_mod_bucket(hash);
-                    let &(ref key, ref value) = bucket.as_ref();
-                    // TODO: handle err?
-                    let _ = self.raw_store_put(key, value);
+                    self.evict(hash)?;
                 }
                 // continue with insert
-                table.insert(hash, (k, v));
-                None
+                table.insert(hash, (k, slot))
+            };
+            Ok(table.bucket_index(&bucket))
+        }
+    }
+
+    /// Writes a bucket to the RawStore in COW mode and marks it as in sync
+    ///
+    /// Buckets whose write fails stay modified and are retried on persist.
+    #[inline]
+    fn write_through(&self, index: usize) -> Result<()> {
+        if !self.mode.is_cow() {
+            return Ok(());
+        }
+        let table = self.raw_table_mut();
+        unsafe {
+            let (key, slot) = table.bucket(index).as_ref();
+            self.raw_store_write(key, slot)?;
+            table.mark_safe(index);
+        }
+        Ok(())
+    }
+
+    /// Evicts a modified bucket if the table is above the modification threshold
+    ///
+    /// Called after an existing bucket of `key` has been touched.
+    #[inline]
+    fn evict_if_needed(&self, key: &K) -> Result<()> {
+        if unlikely(self.raw_table().above_mod_threshold()) {
+            self.evict(make_hash(&self.hash_builder, key))?;
+        }
+        Ok(())
+    }
+
+    /// Moves a modified bucket picked by the [EvictionPolicy] to the RawStore
+    ///
+    /// The bucket stays modified if the write fails.
+    #[inline]
+    fn evict(&self, hash: u64) -> Result<()> {
+        let table = self.raw_table_mut();
+        unsafe {
+            let index = table.find_mod_bucket(hash);
+            let (key, slot) = table.bucket(index).as_ref();
+            self.raw_store_write(key, slot)?;
+            table.release_mod_bucket(index);
+        }
+        Ok(())
+    }
+
+    /// Marks the bucket at `index` as modified
+    ///
+    /// Unlike [HashIndex::evict_if_needed], room is made before the bucket is
+    /// marked, so that a bucket that is handed out is never evicted itself.
+    #[inline]
+    fn touch(&self, index: usize, hash: u64) -> Result<()> {
+        let table = self.raw_table_mut();
+        unsafe {
+            if !table.is_bucket_modified(index) && unlikely(table.above_mod_threshold()) {
+                self.evict(hash)?;
+            }
+            table.touch(index);
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the bucket at `index`, decoding it if needed
+    ///
+    /// Values decoded in compact mode are packed again on the next update.
+    #[inline]
+    fn value_at(&self, index: usize) -> &mut V {
+        let slot = self.slot_at(index);
+        if let Slot::Packed(_) = slot {
+            unsafe { (*self.decoded.get()).push(index) };
+        }
+        slot.value_mut(self.arena()).expect("Unexpected error")
+    }
+
+    /// Insert an encoded value that is in sync with the RawStore
+    ///
+    /// The entry is not marked as modified and will thus not be written back
+    /// unless it is changed.
+    ///
+    /// Returns the index of the bucket.
+    #[inline]
+    fn insert_raw(&self, k: K, raw: Vec<u8>) -> usize {
+        let slot = if self.compact {
+            Slot::Packed(self.arena_mut().alloc(&raw))
+        } else {
+            Slot::Raw(raw)
+        };
+        self.insert_safe_slot(k, slot)
+    }
+
+    /// Insert a Slot that is in sync with the RawStore, see [HashIndex::insert_raw]
+    #[inline]
+    fn insert_safe_slot(&self, k: K, slot: Slot<V>) -> usize {
+        let hash = make_hash(&self.hash_builder, &k);
+        let table = self.raw_table_mut();
+        unsafe {
+            let bucket = table.insert_safe(hash, (k, slot));
+            table.bucket_index(&bucket)
+        }
+    }
+
+    /// Counts a lookup towards the hit ratio
+    #[inline(always)]
+    fn record_lookup(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.set(counter.get() + 1);
+        if let Some(share) = &self.memory {
+            share.record_access();
+        }
+    }
+
+    /// Returns false if the BloomFilter rules out that the key is in the RawStore
+    #[inline]
+    fn may_be_stored(&self, k: &K) -> bool {
+        match self.filter_mut() {
+            Some(filter) => filter.may_contain(make_hash(&self.hash_builder, k)),
+            None => true,
+        }
+    }
+
+    /// Adds a key that has been written to the RawStore to the BloomFilter, if any
+    #[inline]
+    fn record_stored(&self, k: &K) {
+        if let Some(filter) = self.filter_mut() {
+            filter.insert(make_hash(&self.hash_builder, k));
+        }
+    }
+
+    /// Internal helper to get a value from the RawStore
+    #[inline]
+    fn raw_store_get(&self, k: &K) -> Result<Option<V>> {
+        if !self.may_be_stored(k) {
+            return Ok(None);
+        }
+        let raw_store = self.raw_store.borrow_mut();
+        raw_store.get(&self.namespace, k)
+    }
+
+    /// Internal helper to get an encoded value from the RawStore
+    #[inline]
+    fn raw_store_get_raw(&self, k: &K) -> Result<Option<Vec<u8>>> {
+        if !self.may_be_stored(k) {
+            return Ok(None);
+        }
+        let raw_store = self.raw_store.borrow_mut();
+        raw_store.get_raw(&self.namespace, k)
+    }
+
+    /// Internal helper to write a key-value record to the RawStore
+    ///
+    /// Tombstones delete the key from the RawStore.
+    #[inline]
+    fn raw_store_write(&self, k: &K, slot: &Slot<V>) -> Result<()> {
+        let mut raw_store = self.raw_store.borrow_mut();
+        if slot.is_tombstone() {
+            raw_store.delete(&self.namespace, k)
+        } else {
+            raw_store.put_raw(&self.namespace, k, &slot.to_raw(self.arena())?)?;
+            self.record_stored(k);
+            Ok(())
+        }
+    }
+
+    /// Fetches a value from the RawStore and inserts it into the RawTable
+    ///
+    /// Returns whether the key was found.
+    #[inline]
+    fn load(&self, key: &K) -> Result<bool> {
+        if self.decode_mode.is_lazy() {
+            if let Some(raw) = self.raw_store_get_raw(key)? {
+                self.insert_raw(key.clone(), raw);
+                return Ok(true);
+            }
+        } else if let Some(v) = self.raw_store_get(key)? {
+            self.insert(key.clone(), v)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Returns whether the key exists in either the RawTable or the RawStore
+    ///
+    /// In lazy [DecodeMode], a value found in the RawStore is cached in its
+    /// encoded form without being decoded.
+    #[inline]
+    pub fn try_contains_key(&self, key: &K) -> Result<bool> {
+        let hash = make_hash(&self.hash_builder, key);
+        if let Some(bucket) = self.raw_table().find(hash, |x| key.eq(&x.0)) {
+            self.record_lookup(true);
+            return Ok(unsafe { !bucket.as_ref().1.is_tombstone() });
+        }
+        self.record_lookup(false);
+        self.load(key)
+    }
+
+    /// Same as [HashIndex::try_contains_key] but panics on RawStore errors
+    #[inline]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.try_contains_key(key).expect("Unexpected error")
+    }
+
+    /// Looks up a key in the RawTable
+    ///
+    /// Returns `Some(None)` if the key has been removed.
+    #[inline]
+    fn table_get<Q: ?Sized>(&self, k: &Q) -> Result<Option<Option<&V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = make_hash(&self.hash_builder, k);
+        let table = self.raw_table();
+        let item = match table.find(hash, |x| k.eq(x.0.borrow())) {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        unsafe {
+            let slot = &mut item.as_mut().1;
+            if slot.is_tombstone() {
+                return Ok(Some(None));
+            }
+            if let Slot::Packed(_) = slot {
+                // Remember the bucket so that it can be packed again
+                (*self.decoded.get()).push(table.bucket_index(&item));
+            }
+            // Decoding a lazily fetched value does not change its contents,
+            // so the bucket keeps its current meta byte.
+            slot.value(self.arena()).map(|v| Some(Some(v)))
+        }
+    }
+
+    /// Looks up a key in the RawTable and marks its bucket as modified
+    ///
+    /// Returns the index of the bucket.
+    #[inline]
+    fn table_bucket_mut<Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = make_hash(&self.hash_builder, k);
+        let table = self.raw_table_mut();
+        table
+            .find_mut(hash, |x| k.eq(x.0.borrow()))
+            .map(|item| unsafe { table.bucket_index(&item) })
+    }
+
+    #[inline(always)]
+    fn slot_at(&self, index: usize) -> &mut Slot<V> {
+        unsafe { &mut self.raw_table().bucket(index).as_mut().1 }
+    }
+
+    /// Gets the entry of a key for in-place manipulation
+    ///
+    /// Probes the RawTable once and, on a miss, looks the key up in the RawStore once.
+    ///
+    /// ```
+    /// # use britt_marie::{HashIndex, RawStore};
+    /// # use std::{cell::RefCell, rc::Rc};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let raw_store = Rc::new(RefCell::new(RawStore::new(dir.path().to_str().unwrap())));
+    /// let mut counters: HashIndex<u64, u64> = HashIndex::new("_counters", 128, 0.6, raw_store);
+    /// *counters.entry(10).or_insert(0) += 1;
+    /// counters.entry(10).and_modify(|c| *c += 1).or_insert(1);
+    /// ```
+    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V, S>> {
+        self.repack();
+        self.grow_if_full();
+        self.fit_compact_bytes()?;
+        self.grow_filter_if_saturated()?;
+        self.sync_memory()?;
+        let hash = make_hash(&self.hash_builder, &key);
+        if let Some(bucket) = self.raw_table().find(hash, |x| key.eq(&x.0)) {
+            self.record_lookup(true);
+            let index = unsafe { self.raw_table().bucket_index(&bucket) };
+            if unsafe { bucket.as_ref().1.is_tombstone() } {
+                return Ok(Entry::Vacant(VacantEntry::new(
+                    self,
+                    key,
+                    hash,
+                    Some(index),
+                )));
+            }
+            return Ok(Entry::Occupied(OccupiedEntry::new(self, index, hash)));
+        }
+
+        self.record_lookup(false);
+        match self.raw_store_get_raw(&key)? {
+            Some(raw) => {
+                let index = self.insert_raw(key, raw);
+                Ok(Entry::Occupied(OccupiedEntry::new(self, index, hash)))
             }
+            None => Ok(Entry::Vacant(VacantEntry::new(self, key, hash, None))),
         }
     }
 
-    /// Internal helper to get a value from the RawStore
+    /// Same as [HashIndex::try_entry] but panics on RawStore errors
     #[inline]
-    fn raw_store_get(&self, k: &K) -> Result<Option<V>> {
-        let raw_store = self.raw_store.borrow_mut();
-        raw_store.get(k)
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        self.try_entry(key).expect("Unexpected error")
     }
 
-    /// Internal helper to put a key-value record into the RawStore
+    /// Iterates over every entry of the index, including the ones evicted to the RawStore
+    ///
+    /// Entries are yielded in no particular order.
     #[inline]
-    fn raw_store_put(&self, k: &K, v: &V) -> Result<()> {
-        let mut raw_store = self.raw_store.borrow_mut();
-        raw_store.put(k, v)
+    pub fn iter(&mut self) -> Iter<'_, K, V, S> {
+        self.repack();
+        Iter::new(self)
     }
 
+    /// Same as [HashIndex::iter] but yields RawStore and decode errors instead of panicking
     #[inline]
-    fn table_get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        self.get_key_value(k).map(|(_, v)| v)
+    pub fn try_iter(&mut self) -> TryIter<'_, K, V, S> {
+        TryIter { inner: self.iter() }
     }
 
+    /// Iterates over every key of the index, see [HashIndex::iter]
     #[inline]
-    fn table_get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&mut V>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        let hash = make_hash(&self.hash_builder, k);
-        let table = self.raw_table_mut();
-        table
-            .find_mut(hash, |x| k.eq(x.0.borrow()))
-            .map(|item| unsafe { &mut item.as_mut().1 })
+    pub fn keys(&mut self) -> Keys<'_, K, V, S> {
+        Keys { inner: self.iter() }
     }
 
+    /// Iterates over every value of the index, see [HashIndex::iter]
     #[inline]
-    fn get_key_value<Q: ?Sized>(&self, k: &Q) -> Option<(&K, &V)>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        let hash = make_hash(&self.hash_builder, k);
-        let table = self.raw_table();
-        table.find(hash, |x| k.eq(x.0.borrow())).map(|item| unsafe {
-            let &(ref key, ref value) = item.as_ref();
-            (key, value)
-        })
+    pub fn values(&mut self) -> Values<'_, K, V, S> {
+        Values { inner: self.iter() }
     }
 
+    /// Returns the number of entries in the RawTable
     #[inline]
     pub fn len(&self) -> usize {
         self.raw_table().len()
@@ -203,118 +1074,236 @@ where
     pub fn mod_limit(&self) -> usize {
         self.raw_table().mod_limit()
     }
+    /// Returns the share of lookups that were served by the RawTable
+    ///
+    /// Lookups by `get`, `rmw`, `entry` and `contains_key` are counted.
+    #[inline]
+    pub fn hit_ratio(&self) -> f64 {
+        let hits = self.hits.get();
+        let lookups = hits + self.misses.get();
+        if lookups == 0 {
+            return 0.0;
+        }
+        hits as f64 / lookups as f64
+    }
     #[inline]
     pub fn capacity(&self) -> usize {
         self.raw_table().capacity()
     }
 }
 
-impl<K, V> IndexOps for HashIndex<K, V>
+impl<K, V, S> HashIndex<K, V, S>
 where
     K: Key + Eq + Hash,
     V: Value,
+    S: BuildHasher,
 {
-    fn persist(&self) -> Result<()> {
-        if self.mode.is_lazy() {
-            let table = self.raw_table_mut();
-            unsafe {
-                let mut raw_store = self.raw_store.borrow_mut();
-                // TODO: use raw_store.put_batch(..)?;
-                for bucket in table.iter_modified() {
-                    let &(ref key, ref value) = bucket.as_ref();
-                    raw_store.put(key, value)?;
+    /// Writes the modified entries to the RawStore in one atomic batch
+    fn persist_modified(&self) -> Result<()> {
+        // In COW mode, only entries whose write failed are left modified
+        let table = self.raw_table_mut();
+        unsafe {
+            let modified: Vec<usize> = table
+                .iter_modified()
+                .map(|bucket| table.bucket_index(&bucket))
+                .collect();
+            if modified.is_empty() {
+                return Ok(());
+            }
+            let mut records = Vec::with_capacity(modified.len());
+            for &index in modified.iter() {
+                let (key, slot) = table.bucket(index).as_ref();
+                // Tombstones delete the key
+                let raw_value = if slot.is_tombstone() {
+                    None
+                } else {
+                    Some(slot.to_raw(self.arena())?)
+                };
+                records.push((key, raw_value));
+            }
+            // Buckets stay modified unless the whole batch has been written
+            self.raw_store
+                .borrow_mut()
+                .write_batch(&self.namespace, records)?;
+            for index in modified {
+                let (key, slot) = table.bucket(index).as_ref();
+                if !slot.is_tombstone() {
+                    self.record_stored(key);
                 }
-            };
+                table.clear_modified(index);
+            }
+        };
+        Ok(())
+    }
+
+    /// Records the hottest keys in the RawTable, see [HashIndex::with_hot_keys]
+    fn record_hot_keys(&self) -> Result<()> {
+        if self.hot_keys == 0 {
+            return Ok(());
+        }
+        let table = self.raw_table();
+        let mut candidates: Vec<(u64, &K)> = unsafe {
+            table
+                .iter()
+                .filter(|bucket| !bucket.as_ref().1.is_tombstone())
+                .map(|bucket| (table.heat(table.bucket_index(&bucket)), &bucket.as_ref().0))
+                .collect()
+        };
+        candidates.sort_by_key(|candidate| Reverse(candidate.0));
+        let keys = candidates
+            .into_iter()
+            .take(self.hot_keys)
+            .map(|(_, key)| key.into_raw())
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+        self.raw_store.borrow_mut().put_bytes(
+            HOT_KEYS_NAMESPACE,
+            &self.namespace,
+            &encode_key_list(&keys),
+        )
+    }
+}
+
+impl<K, V, S> IndexOps for HashIndex<K, V, S>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+    S: BuildHasher,
+{
+    fn persist(&self) -> Result<()> {
+        self.persist_modified()?;
+        self.record_hot_keys()
+    }
+
+    fn enforce_budget(&mut self) -> Result<()> {
+        let share = match &self.memory {
+            Some(share) => share.clone(),
+            None => return Ok(()),
+        };
+        if let Some(target) = share.take_target() {
+            // Decoded buckets are remembered by index, which shrinking changes
+            self.repack();
+            self.shrink_to(target)?;
+            share.set_bytes(self.memory_size());
         }
-        // Else just ignore as COW copies on each modification
         Ok(())
     }
 }
 
-impl<K, V> HashOps<K, V> for HashIndex<K, V>
+impl<K, V, S> HashOps<K, V> for HashIndex<K, V, S>
 where
     K: Key + Eq + Hash,
     V: Value,
+    S: BuildHasher,
 {
     #[inline(always)]
-    fn get(&self, key: &K) -> Option<&V> {
-        let entry = self.table_get(key);
-
+    fn try_get(&self, key: &K) -> Result<Option<&V>> {
         // Return early if we have a match on our RawTable
-        if entry.is_some() {
-            return entry;
+        if let Some(entry) = self.table_get(key)? {
+            self.record_lookup(true);
+            return Ok(entry);
         }
+        self.record_lookup(false);
 
         // Attempt to find the value in the RawStore
-        if let Ok(entry_opt) = self.raw_store_get(key) {
-            if let Some(v) = entry_opt {
-                // Insert the value back into the index
-                let _ = self.insert(key.clone(), v);
-                // Kinda silly but run table_get again to get the referenced value.
-                // Cannot return a referenced value created in the function itself...
-                self.table_get(key)
-            } else {
-                // The key does not exist
-                return None;
-            }
+        if self.load(key)? {
+            // Kinda silly but run table_get again to get the referenced value.
+            // Cannot return a referenced value created in the function itself...
+            Ok(self.table_get(key)?.flatten())
         } else {
-            // TODO: Match on error and see if there is something that can be done?
-            // otherwise panic?
-            panic!("Unexpected error");
+            // The key does not exist
+            Ok(None)
         }
     }
     #[inline(always)]
-    fn put(&mut self, key: K, value: V) {
-        let _ = self.insert(key, value);
+    fn try_put(&mut self, key: K, value: V) -> Result<()> {
+        self.repack();
+        self.grow_if_full();
+        self.fit_compact_bytes()?;
+        self.grow_filter_if_saturated()?;
+        self.sync_memory()?;
+        if let Some(share) = &self.memory {
+            share.record_access();
+        }
+        let index = self.insert(key, value)?;
+        self.write_through(index)
     }
 
     #[inline(always)]
-    fn rmw<F: Sized>(&mut self, key: &K, mut f: F) -> bool
+    fn try_rmw<F: Sized>(&mut self, key: &K, mut f: F) -> Result<bool>
     where
         F: FnMut(&mut V),
     {
-        if let Some(mut entry) = self.table_get_mut(key) {
+        self.repack();
+        self.grow_if_full();
+        self.fit_compact_bytes()?;
+        self.grow_filter_if_saturated()?;
+        self.sync_memory()?;
+        let arena = self.arena_mut();
+        if let Some(index) = self.table_bucket_mut(key) {
+            self.record_lookup(true);
+            let slot = self.slot_at(index);
+            if slot.is_tombstone() {
+                // The key has been removed
+                return Ok(false);
+            }
             // run the udf on the data
-            f(&mut entry);
-            if self.mode.is_cow() {
-                // TODO
+            f(slot.value_mut(arena)?);
+            if self.compact {
+                slot.pack(arena)?;
             }
+            self.write_through(index)?;
 
             // as we have touched `key` through table_get_mut,
             // check whether we are above the modifcation limit,
             // and proceed to evict bucket if that is the case.
-            let table = self.raw_table_mut();
-            if unlikely(table.above_mod_threshold()) {
-                unsafe {
-                    let hash = make_hash(&self.hash_builder, &key);
-                    let bucket = table.evict_mod_bucket(hash);
-                    let &(ref key, ref value) = bucket.as_ref();
-                    // TODO: handle err?
-                    let _ = self.raw_store_put(key, value);
-                };
-            }
+            self.evict_if_needed(key)?;
 
             // indicate that the operation was successful
-            return true;
+            return Ok(true);
         }
 
         // Attempt to find the value in the RawStore
-        if let Ok(entry_opt) = self.raw_store_get(key) {
-            if let Some(mut value) = entry_opt {
-                // run the rmw op on the value
-                f(&mut value);
-                if self.mode.is_cow() {
-                    // TODO
-                }
-                // insert the value into the RawTable
-                let _ = self.insert(key.clone(), value);
-                // indicate that the operation was successful
-                return true;
-            }
+        self.record_lookup(false);
+        if let Some(mut value) = self.raw_store_get(key)? {
+            // run the rmw op on the value
+            f(&mut value);
+            // insert the value into the RawTable
+            let index = self.insert(key.clone(), value)?;
+            self.write_through(index)?;
+            // indicate that the operation was successful
+            return Ok(true);
         }
 
         // return false as the rmw operation did not modify the given key
-        return false;
+        Ok(false)
+    }
+
+    #[inline]
+    fn try_remove(&mut self, key: &K) -> Result<Option<V>> {
+        self.repack();
+        self.grow_if_full();
+        self.fit_compact_bytes()?;
+        self.grow_filter_if_saturated()?;
+        self.sync_memory()?;
+        if let Some(index) = self.table_bucket_mut(key) {
+            // Keep a tombstone so that the key is deleted from the RawStore
+            // and not fetched again by later reads.
+            let old = std::mem::replace(self.slot_at(index), Slot::Tombstone);
+            let value = old.into_value(self.arena())?;
+            self.write_through(index)?;
+            self.evict_if_needed(key)?;
+            return Ok(value);
+        }
+
+        // The key may have been evicted to the RawStore
+        match self.raw_store_get(key)? {
+            Some(value) => {
+                let index = self.insert_slot(key.clone(), Slot::Tombstone)?;
+                self.write_through(index)?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
     }
 }
 
@@ -331,7 +1320,7 @@ mod tests {
         let mod_factor: f32 = 0.4;
         let capacity = 4;
         let mut hash_index: HashIndex<u64, u64> =
-            HashIndex::new(capacity, mod_factor, raw_store.clone());
+            HashIndex::new("index", capacity, mod_factor, raw_store.clone());
         for i in 0..1024 {
             hash_index.put(i as u64, i as u64);
             let key: u64 = i as u64;
@@ -340,4 +1329,533 @@ mod tests {
         assert_eq!(hash_index.persist().is_ok(), true);
         assert_eq!(raw_store.borrow_mut().checkpoint().is_ok(), true);
     }
+
+    #[test]
+    fn lazy_decode_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 128, 0.5, raw_store.clone());
+        for i in 0..64 {
+            hash_index.put(i, i * 10);
+        }
+        assert_eq!(hash_index.persist().is_ok(), true);
+
+        let lazy_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 128, 0.5, raw_store).with_decode_mode(DecodeMode::Lazy);
+        assert!(lazy_index.contains_key(&5));
+        assert!(!lazy_index.contains_key(&1000));
+
+        // The fetched value is kept encoded and is not marked as modified
+        let hash = make_hash(&lazy_index.hash_builder, &5u64);
+        let bucket = lazy_index.raw_table().find(hash, |x| x.0 == 5).unwrap();
+        assert!(matches!(unsafe { &bucket.as_ref().1 }, Slot::Raw(_)));
+        assert_eq!(unsafe { lazy_index.raw_table_mut().iter_modified().count() }, 0);
+
+        // First typed access decodes it
+        assert_eq!(lazy_index.get(&5), Some(&50));
+        assert!(matches!(unsafe { &bucket.as_ref().1 }, Slot::Value(_)));
+        assert_eq!(lazy_index.get(&6), Some(&60));
+        assert_eq!(unsafe { lazy_index.raw_table_mut().iter_modified().count() }, 0);
+    }
+
+    #[test]
+    fn compact_values_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, String> =
+            HashIndex::new("index", 16, 0.5, raw_store).with_compact_values(1 << 20);
+        for i in 0..256u64 {
+            hash_index.put(i, i.to_string());
+        }
+        assert!(hash_index.rmw(&255, |v| v.push('!')));
+
+        let packed = |index: &HashIndex<u64, String>, key: u64| {
+            let hash = make_hash(&index.hash_builder, &key);
+            let bucket = index.raw_table().find(hash, |x| x.0 == key).unwrap();
+            matches!(unsafe { &bucket.as_ref().1 }, Slot::Packed(_))
+        };
+        assert!(packed(&hash_index, 255));
+        assert_eq!(hash_index.get(&255), Some(&String::from("255!")));
+        assert!(!packed(&hash_index, 255));
+
+        // The next update packs the decoded value again
+        hash_index.put(1000, String::from("1000"));
+        assert!(packed(&hash_index, 255));
+
+        // Evicted entries are written from their packed form
+        for i in 0..255u64 {
+            assert_eq!(hash_index.get(&i), Some(&i.to_string()));
+        }
+    }
+
+    #[test]
+    fn compact_bytes_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let max_bytes = 32 << 10;
+        let value = |i: u64| format!("{:064}", i);
+        let mut hash_index: HashIndex<u64, String> =
+            HashIndex::new("index", 16, 0.5, raw_store).with_compact_values(max_bytes);
+        for i in 0..4096u64 {
+            hash_index.put(i, value(i));
+        }
+        // The table grows past its initial capacity as far as the budget allows
+        assert!(hash_index.capacity() > 16);
+        hash_index.persist().unwrap();
+        hash_index.fit_compact_bytes().unwrap();
+        assert!(hash_index.memory_size() <= max_bytes);
+        for i in 0..4096u64 {
+            assert_eq!(hash_index.get(&i), Some(&value(i)));
+        }
+    }
+
+    #[test]
+    fn remove_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 16, 0.5, raw_store.clone());
+        for i in 0..256u64 {
+            hash_index.put(i, i);
+        }
+        // Entries both in memory and evicted to the RawStore
+        for i in (0..256u64).step_by(2) {
+            assert_eq!(hash_index.remove(&i), Some(i));
+            assert_eq!(hash_index.remove(&i), None);
+            assert_eq!(hash_index.get(&i), None);
+            assert!(!hash_index.contains_key(&i));
+            assert!(!hash_index.rmw(&i, |v| *v += 1));
+        }
+        assert_eq!(hash_index.persist().is_ok(), true);
+
+        // Removals have reached the RawStore
+        let reloaded: HashIndex<u64, u64> = HashIndex::new("index", 16, 0.5, raw_store.clone());
+        for i in 0..256u64 {
+            let expected = if i % 2 == 0 { None } else { Some(i) };
+            assert_eq!(reloaded.get(&i).copied(), expected);
+            let stored: Option<u64> = raw_store.borrow_mut().get(b"index", &i).unwrap();
+            assert_eq!(stored, expected);
+        }
+    }
+
+    #[test]
+    fn iter_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut other: HashIndex<u64, u64> = HashIndex::new("other", 16, 0.5, raw_store.clone());
+        other.put(1000, 1000);
+        assert_eq!(other.persist().is_ok(), true);
+
+        let mut hash_index: HashIndex<u64, u64> = HashIndex::new("index", 16, 0.5, raw_store)
+            .with_eviction_policy(EvictionPolicy::Lru { samples: 4 });
+        for i in 0..512u64 {
+            hash_index.put(i, i);
+        }
+        // Updates and removals of evicted keys must win over the RawStore
+        for i in 0..512u64 {
+            if i % 3 == 0 {
+                hash_index.remove(&i);
+            } else {
+                hash_index.rmw(&i, |v| *v += 1);
+            }
+        }
+        let mut entries: Vec<(u64, u64)> = hash_index.iter().collect();
+        entries.sort();
+        let expected: Vec<(u64, u64)> = (0..512u64)
+            .filter(|i| i % 3 != 0)
+            .map(|i| (i, i + 1))
+            .collect();
+        assert_eq!(entries, expected);
+
+        assert_eq!(hash_index.keys().count(), expected.len());
+        let sum: u64 = hash_index.values().sum();
+        assert_eq!(sum, expected.iter().map(|(_, v)| v).sum());
+
+        // Iterating does not count as an access to the entries in memory
+        let heat = |index: &HashIndex<u64, u64>| -> Vec<u64> {
+            let table = index.raw_table();
+            unsafe {
+                table
+                    .iter()
+                    .map(|bucket| table.heat(table.bucket_index(&bucket)))
+                    .collect()
+            }
+        };
+        let before = heat(&hash_index);
+        assert_eq!(hash_index.iter().count(), expected.len());
+        assert_eq!(heat(&hash_index), before);
+    }
+
+    #[test]
+    fn entry_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 16, 0.5, raw_store.clone()).with_compact_values(1 << 20);
+        for i in 0..256u64 {
+            *hash_index.entry(i).or_default() += i;
+        }
+        // Hits in both the RawTable and the RawStore
+        for i in 0..512u64 {
+            hash_index.entry(i).and_modify(|v| *v += 1).or_insert(0);
+        }
+        assert_eq!(hash_index.entry(7).key(), &7);
+        match hash_index.entry(3) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove(), 4),
+            Entry::Vacant(_) => panic!("key 3 exists"),
+        }
+        assert!(matches!(hash_index.entry(3), Entry::Vacant(_)));
+        assert_eq!(*hash_index.entry(3).or_insert_with(|| 30), 30);
+        assert_eq!(hash_index.persist().is_ok(), true);
+
+        let reloaded: HashIndex<u64, u64> = HashIndex::new("index", 16, 0.5, raw_store);
+        for i in 0..512u64 {
+            let expected = match i {
+                3 => 30,
+                i if i < 256 => i + 1,
+                _ => 0,
+            };
+            assert_eq!(reloaded.get(&i), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn cow_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::cow("index", 16, 0.5, raw_store.clone());
+        for i in 0..64u64 {
+            hash_index.put(i, i);
+        }
+        assert!(hash_index.rmw(&1, |v| *v += 10));
+        assert_eq!(hash_index.remove(&2), Some(2));
+        // Writes through the entry API happen once the borrow ends
+        *hash_index.entry(3).or_insert(0) += 30;
+        hash_index.entry(4).and_modify(|v| *v += 40);
+        *hash_index.entry(100).or_default() += 100;
+
+        // Nothing is left to persist
+        assert_eq!(
+            unsafe { hash_index.raw_table_mut().iter_modified().count() },
+            0
+        );
+
+        // Updates went straight to the RawStore without a persist
+        let stored =
+            |key: u64| -> Option<u64> { raw_store.borrow_mut().get(b"index", &key).unwrap() };
+        assert_eq!(stored(0), Some(0));
+        assert_eq!(stored(1), Some(11));
+        assert_eq!(stored(2), None);
+        assert_eq!(stored(3), Some(33));
+        assert_eq!(stored(4), Some(44));
+        assert_eq!(stored(63), Some(63));
+        assert_eq!(stored(100), Some(100));
+    }
+
+    #[test]
+    fn resize_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let max_bytes = RawTable::<(u64, Slot<u64>)>::allocation_size(1024).unwrap();
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 16, 0.5, raw_store.clone())
+                .with_resize_policy(ResizePolicy::Grow { max_bytes });
+        for i in 0..512u64 {
+            hash_index.put(i, i);
+        }
+        // The working set fits in memory
+        assert_eq!(hash_index.len(), 512);
+        assert!(hash_index.capacity() >= 512);
+
+        // Growing stops at the budget
+        for i in 512..4096u64 {
+            hash_index.put(i, i);
+        }
+        assert!(hash_index.capacity() >= 1024);
+        assert!(hash_index.capacity() < 2048);
+
+        // Modified entries stay modified across resizes
+        assert_eq!(hash_index.persist().is_ok(), true);
+        let reloaded: HashIndex<u64, u64> = HashIndex::new("index", 16, 0.5, raw_store);
+        for i in 0..4096u64 {
+            assert_eq!(reloaded.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn hasher_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<String, u64, RandomHashBuilder> =
+            HashIndex::new("index", 16, 0.5, raw_store).with_hasher(RandomHashBuilder::new());
+        for i in 0..256u64 {
+            hash_index.put(i.to_string(), i);
+        }
+        assert!(hash_index.rmw(&String::from("1"), |v| *v += 1));
+        for i in 2..256u64 {
+            assert_eq!(hash_index.get(&i.to_string()), Some(&i));
+        }
+        assert_eq!(hash_index.get(&String::from("1")), Some(&2));
+    }
+
+    #[test]
+    fn eviction_test() {
+        let policies = [
+            EvictionPolicy::Probe,
+            EvictionPolicy::Clock,
+            EvictionPolicy::Lru { samples: 8 },
+            EvictionPolicy::Lfu { samples: 8 },
+        ];
+        for &policy in policies.iter() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().to_str().unwrap();
+            let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+            let mut hash_index: HashIndex<u64, u64> =
+                HashIndex::new("index", 64, 0.5, raw_store).with_eviction_policy(policy);
+            for i in 0..1024u64 {
+                hash_index.put(i, i);
+            }
+            for i in 0..16u64 {
+                assert!(hash_index.rmw(&i, |v| *v += 1));
+            }
+
+            // A few hot keys are read between every cold one
+            for round in 0..256u64 {
+                for i in 0..8u64 {
+                    assert_eq!(hash_index.get(&i), Some(&(i + 1)));
+                }
+                let cold = 16 + round * 3;
+                assert_eq!(hash_index.get(&cold), Some(&cold));
+            }
+            if policy != EvictionPolicy::Probe {
+                assert!(hash_index.hit_ratio() > 0.8, "{:?}", policy);
+            }
+
+            for i in 0..1024u64 {
+                let expected = if i < 16 { i + 1 } else { i };
+                assert_eq!(hash_index.get(&i), Some(&expected));
+            }
+        }
+    }
+
+    #[test]
+    fn memory_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let table_size = RawTable::<(u64, Slot<u64>)>::allocation_size(1024).unwrap();
+        let budget = table_size * 7 / 4;
+        raw_store.borrow_mut().set_memory_budget(budget);
+
+        let mut cold: HashIndex<u64, u64> = HashIndex::new("cold", 1024, 0.5, raw_store.clone());
+        for i in 0..800u64 {
+            cold.put(i, i);
+        }
+        // The second index exceeds the budget. It is squeezed while it has
+        // fewer accesses than the first one, then asks for room to grow.
+        let mut hot: HashIndex<u64, u64> = HashIndex::new("hot", 1024, 0.5, raw_store.clone());
+        for i in 0..2000u64 {
+            hot.put(i, i);
+        }
+        assert!(hot.capacity() < 1024);
+
+        // The cold index shrinks without being updated, leaving room for the hot one
+        assert!(cold.enforce_budget().is_ok());
+        assert!(cold.capacity() < 1024);
+        cold.put(800, 800);
+        hot.put(2000, 2000);
+        assert!(cold.capacity() < 1024);
+        assert!(hot.capacity() >= 1024);
+        let exceeded = raw_store
+            .borrow_mut()
+            .memory_manager()
+            .unwrap()
+            .is_exceeded();
+        assert!(!exceeded);
+        for i in 0..=800u64 {
+            assert_eq!(cold.get(&i), Some(&i));
+        }
+        for i in 0..=2000u64 {
+            assert_eq!(hot.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn bloom_filter_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 16, 0.5, raw_store.clone());
+        for i in 0..256u64 {
+            hash_index.put(i, i);
+        }
+        assert!(hash_index.persist().is_ok());
+
+        // The filter of a restored index holds the keys that are already stored
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 16, 0.5, raw_store).with_bloom_filter(64, 0.01);
+        for i in 0..256u64 {
+            assert_eq!(hash_index.get(&i), Some(&i));
+        }
+        let false_positives = (1000..2000u64)
+            .filter(|i| hash_index.may_be_stored(i))
+            .count();
+        assert!(false_positives < 50, "{}", false_positives);
+
+        // Evicted keys are added, saturating the filter until it is rebuilt
+        for i in 256..2048u64 {
+            hash_index.put(i, i);
+        }
+        for i in 0..2048u64 {
+            assert_eq!(hash_index.get(&i), Some(&i));
+        }
+        assert_eq!(hash_index.get(&5000), None);
+
+        hash_index.remove(&0);
+        assert!(hash_index.persist().is_ok());
+        assert!(hash_index.rebuild_filter().is_ok());
+        assert_eq!(hash_index.get(&0), None);
+    }
+
+    #[test]
+    fn warm_up_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 64, 0.5, raw_store.clone())
+                .with_eviction_policy(EvictionPolicy::Lfu { samples: 8 })
+                .with_hot_keys(8);
+        for i in 0..256u64 {
+            hash_index.put(i, i);
+        }
+        for _ in 0..100 {
+            for i in 0..8u64 {
+                assert_eq!(hash_index.get(&i), Some(&i));
+            }
+        }
+        assert!(hash_index.persist().is_ok());
+
+        // The hot keys of the last persist are loaded without being marked as modified
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 64, 0.5, raw_store.clone()).with_hot_keys(8);
+        assert_eq!(hash_index.warm_up().unwrap(), 8);
+        assert_eq!(hash_index.len(), 8);
+        let modified = unsafe { hash_index.raw_table().iter_modified().count() };
+        assert_eq!(modified, 0);
+        for i in 0..8u64 {
+            assert_eq!(hash_index.get(&i), Some(&i));
+        }
+        assert_eq!(hash_index.misses.get(), 0);
+
+        // Keys that are loaded already or not stored are skipped
+        assert_eq!(hash_index.warm_up_keys(vec![0, 100, 101, 5000]).unwrap(), 2);
+        assert_eq!(hash_index.len(), 10);
+
+        // Loading stops once the RawTable is full
+        let mut hash_index: HashIndex<u64, u64> = HashIndex::new("index", 16, 0.5, raw_store);
+        let loaded = hash_index.warm_up_keys(0..256u64).unwrap();
+        assert_eq!(loaded, hash_index.capacity());
+        assert_eq!(hash_index.len(), loaded);
+    }
+
+    /// Cipher that passes values through, or fails while `failing` is set
+    struct FlakyCipher {
+        failing: Rc<Cell<bool>>,
+    }
+
+    impl crate::raw_store::cipher::ValueCipher for FlakyCipher {
+        fn encrypt(&self, plaintext: &[u8], _aad: &[u8]) -> Result<Vec<u8>> {
+            if self.failing.get() {
+                return Err(BrittMarieError::Cipher(String::from("unavailable")));
+            }
+            Ok(plaintext.to_vec())
+        }
+        fn decrypt(&self, ciphertext: &[u8], _aad: &[u8]) -> Result<Vec<u8>> {
+            Ok(ciphertext.to_vec())
+        }
+        fn needs_reencrypt(&self, _ciphertext: &[u8]) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn persist_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let failing = Rc::new(Cell::new(false));
+        raw_store.borrow_mut().set_cipher(FlakyCipher {
+            failing: failing.clone(),
+        });
+        let mut hash_index: HashIndex<u64, u64> =
+            HashIndex::new("index", 64, 0.9, raw_store.clone());
+        for i in 0..32u64 {
+            hash_index.put(i, i);
+        }
+        assert!(hash_index.persist().is_ok());
+        for i in 0..16u64 {
+            hash_index.put(i, i + 100);
+        }
+        hash_index.remove(&20);
+
+        // A failed persist writes nothing and keeps every entry modified
+        failing.set(true);
+        assert!(hash_index.persist().is_err());
+        let modified = unsafe { hash_index.raw_table().iter_modified().count() };
+        assert_eq!(modified, 17);
+        for i in 0..16u64 {
+            let stored: Option<u64> = raw_store.borrow_mut().get(b"index", &i).unwrap();
+            assert_eq!(stored, Some(i));
+        }
+
+        failing.set(false);
+        assert!(hash_index.persist().is_ok());
+        let modified = unsafe { hash_index.raw_table().iter_modified().count() };
+        assert_eq!(modified, 0);
+        for i in 0..16u64 {
+            let stored: Option<u64> = raw_store.borrow_mut().get(b"index", &i).unwrap();
+            assert_eq!(stored, Some(i + 100));
+        }
+        let stored: Option<u64> = raw_store.borrow_mut().get(b"index", &20u64).unwrap();
+        assert_eq!(stored, None);
+    }
+
+    #[test]
+    fn try_ops_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        // A record that cannot be decoded
+        raw_store
+            .borrow_mut()
+            .put_raw(b"index", &5u64, &[0xff])
+            .unwrap();
+
+        let mut hash_index: HashIndex<u64, u64> = HashIndex::new("index", 16, 0.5, raw_store);
+        assert!(hash_index.try_put(1, 1).is_ok());
+        assert_eq!(hash_index.try_get(&1).unwrap(), Some(&1));
+        assert_eq!(hash_index.try_get(&2).unwrap(), None);
+        assert!(hash_index.try_get(&5).is_err());
+        assert!(hash_index.try_rmw(&5, |v| *v += 1).is_err());
+        assert!(hash_index.try_remove(&5).is_err());
+        assert!(hash_index.try_contains_key(&5).is_err());
+        assert!(hash_index.try_rmw(&1, |v| *v += 1).unwrap());
+        assert!(hash_index.try_contains_key(&1).unwrap());
+        assert_eq!(hash_index.try_remove(&1).unwrap(), Some(2));
+        assert!(hash_index.try_entry(2).is_ok());
+        assert!(hash_index.try_iter().any(|entry| entry.is_err()));
+    }
 }
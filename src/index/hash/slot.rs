@@ -0,0 +1,102 @@
+use crate::data::Value;
+use crate::error::*;
+use crate::index::hash::arena::{Arena, ArenaSlice};
+use std::borrow::Cow;
+
+/// Value holder of a RawTable bucket
+///
+/// A Slot either carries the decoded value or its encoded form, which is
+/// decoded on first typed access. Encoded bytes are kept either as they were
+/// read from the RawStore or packed into the index's [Arena]. Removed keys
+/// keep a tombstone until the removal has reached the RawStore.
+pub(crate) enum Slot<V>
+where
+    V: Value,
+{
+    /// Decoded value
+    Value(V),
+    /// Encoded bytes that have not been accessed yet
+    Raw(Vec<u8>),
+    /// Encoded bytes stored in the Arena
+    Packed(ArenaSlice),
+    /// The key has been removed
+    Tombstone,
+}
+
+impl<V> Slot<V>
+where
+    V: Value,
+{
+    #[inline]
+    pub fn is_tombstone(&self) -> bool {
+        matches!(self, Slot::Tombstone)
+    }
+
+    /// Returns a reference to the value, decoding it first if needed
+    #[inline]
+    pub fn value(&mut self, arena: &Arena) -> Result<&V> {
+        self.value_mut(arena).map(|v| &*v)
+    }
+
+    /// Returns a mutable reference to the value, decoding it first if needed
+    #[inline]
+    pub fn value_mut(&mut self, arena: &Arena) -> Result<&mut V> {
+        match self {
+            Slot::Raw(bytes) => *self = Slot::Value(V::from_raw(bytes)?),
+            Slot::Packed(slice) => *self = Slot::Value(V::from_raw(arena.get(*slice))?),
+            Slot::Value(_) => (),
+            Slot::Tombstone => unreachable!("removed values are never accessed"),
+        }
+        match self {
+            Slot::Value(v) => Ok(v),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Encodes a decoded value into the Arena
+    #[inline]
+    pub fn pack(&mut self, arena: &mut Arena) -> Result<()> {
+        let slice = match self {
+            Slot::Value(v) => arena.alloc(&v.into_raw()?),
+            Slot::Raw(bytes) => arena.alloc(bytes),
+            Slot::Packed(_) | Slot::Tombstone => return Ok(()),
+        };
+        *self = Slot::Packed(slice);
+        Ok(())
+    }
+
+    /// Returns the encoded form of the value
+    ///
+    /// Slots that were never decoded are handed out as is.
+    #[inline]
+    pub fn to_raw<'a>(&'a self, arena: &'a Arena) -> Result<Cow<'a, [u8]>> {
+        match self {
+            Slot::Value(v) => Ok(Cow::Owned(v.into_raw()?)),
+            Slot::Raw(bytes) => Ok(Cow::Borrowed(bytes)),
+            Slot::Packed(slice) => Ok(Cow::Borrowed(arena.get(*slice))),
+            Slot::Tombstone => unreachable!("removed values are never written"),
+        }
+    }
+
+    /// Returns a copy of the value, decoding it if needed
+    #[inline]
+    pub fn to_value(&self, arena: &Arena) -> Result<V> {
+        match self {
+            Slot::Value(v) => Ok(v.clone()),
+            Slot::Raw(bytes) => V::from_raw(bytes),
+            Slot::Packed(slice) => V::from_raw(arena.get(*slice)),
+            Slot::Tombstone => unreachable!("removed values are never accessed"),
+        }
+    }
+
+    /// Takes the value out of the Slot, `None` for a tombstone
+    #[inline]
+    pub fn into_value(self, arena: &Arena) -> Result<Option<V>> {
+        match self {
+            Slot::Value(v) => Ok(Some(v)),
+            Slot::Raw(bytes) => V::from_raw(&bytes).map(Some),
+            Slot::Packed(slice) => V::from_raw(arena.get(slice)).map(Some),
+            Slot::Tombstone => Ok(None),
+        }
+    }
+}
@@ -9,12 +9,14 @@ use core::hint;
 use core::iter::FusedIterator;
 use core::marker::PhantomData;
 use core::mem;
-use core::ptr::NonNull;
+use core::ptr::{self, NonNull};
 use std::alloc::{alloc, dealloc, handle_alloc_error};
+use std::cell::Cell;
 
 use crate::hint::{likely, unlikely};
 use crate::index::hash::bitmask::BitMask;
 use crate::index::hash::imp::Group;
+use crate::index::EvictionPolicy;
 
 /// Augments `AllocErr` with a `CapacityOverflow` variant.
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -81,6 +83,12 @@ const MODIFIED_TOUCHED: u8 = 0b1100_0000;
 const SAFE: u8 = 0b0000_0000;
 const SAFE_TOUCHED: u8 = 0b0100_0000;
 
+/// Meta bit that is set on access.
+const TOUCHED: u8 = 0b0100_0000;
+
+/// Number of accesses per bucket after which LFU access counts are halved.
+const LFU_DECAY_PERIOD: u64 = 8;
+
 /// Checks whether a control byte represents a full bucket (top bit is clear).
 #[inline]
 fn is_full(ctrl: u8) -> bool {
@@ -102,7 +110,6 @@ fn special_is_empty(ctrl: u8) -> bool {
 
 /// Checks whether a meta byte represents a modified bucket (top bit is set).
 #[inline]
-#[allow(dead_code)]
 fn is_modified(meta: u8) -> bool {
     meta & 0x80 != 0
 }
@@ -254,6 +261,14 @@ fn calculate_layout<T>(buckets: usize) -> Option<(Layout, usize)> {
     ))
 }
 
+/// Returns the Layout of the meta bytes of a table with the given number of buckets.
+#[inline]
+fn meta_layout(buckets: usize) -> Layout {
+    // There is no possible overflow here since buckets is a power of two and
+    // Group::WIDTH is a small number.
+    unsafe { Layout::from_size_align_unchecked(buckets + Group::WIDTH, Group::WIDTH) }
+}
+
 /// A reference to a hash table bucket containing a `T`.
 ///
 /// This is usually just a pointer to the element itself. However if the element
@@ -356,6 +371,21 @@ pub struct RawTable<T> {
     //
     mod_limit: usize,
 
+    // Share of the capacity that may be modified, kept to resize the table
+    mod_factor: f32,
+
+    // Policy that picks the buckets to evict
+    eviction: EvictionPolicy,
+
+    // Next bucket to visit in EvictionPolicy::Clock
+    hand: usize,
+
+    // Logical time, counted in accesses
+    tick: Cell<u64>,
+
+    // Last access time (LRU) or access count (LFU) of each bucket
+    stamps: Vec<Cell<u64>>,
+
     // Tell dropck that we own instances of T.
     marker: PhantomData<T>,
 }
@@ -377,6 +407,11 @@ impl<T> RawTable<T> {
             growth_left: 0,
             mod_counter: 0,
             mod_limit: 0,
+            mod_factor: 0.0,
+            eviction: EvictionPolicy::default(),
+            hand: 0,
+            tick: Cell::new(0),
+            stamps: Vec::new(),
             marker: PhantomData,
         }
     }
@@ -394,9 +429,11 @@ impl<T> RawTable<T> {
         let (layout, ctrl_offset) =
             calculate_layout::<T>(buckets).ok_or_else(|| fallability.capacity_overflow())?;
         let ctrl_ptr = NonNull::new(alloc(layout)).ok_or_else(|| fallability.alloc_err(layout))?;
-        let meta_ptr = NonNull::new(alloc(layout)).ok_or_else(|| fallability.alloc_err(layout))?;
+        // Meta bytes mirror the control bytes, without any buckets in front of them
+        let meta_layout = meta_layout(buckets);
+        let meta =
+            NonNull::new(alloc(meta_layout)).ok_or_else(|| fallability.alloc_err(meta_layout))?;
         let ctrl = NonNull::new_unchecked(ctrl_ptr.as_ptr().add(ctrl_offset));
-        let meta = NonNull::new_unchecked(meta_ptr.as_ptr().add(ctrl_offset));
         let growth_left = bucket_mask_to_capacity(buckets - 1);
 
         Ok(Self {
@@ -406,7 +443,12 @@ impl<T> RawTable<T> {
             items: 0,
             mod_counter: 0,
             mod_limit: (growth_left as f32 * mod_factor) as usize,
+            mod_factor,
             growth_left,
+            eviction: EvictionPolicy::default(),
+            hand: 0,
+            tick: Cell::new(0),
+            stamps: Vec::new(),
             marker: PhantomData,
         })
     }
@@ -444,12 +486,104 @@ impl<T> RawTable<T> {
             .unwrap_or_else(|_| unsafe { hint::unreachable_unchecked() })
     }
 
+    /// Sets the policy that picks the buckets to evict.
+    ///
+    /// Access history is only kept by the sampling policies and starts out empty.
+    pub fn set_eviction_policy(&mut self, eviction: EvictionPolicy) {
+        if let EvictionPolicy::Lru { samples } | EvictionPolicy::Lfu { samples } = eviction {
+            assert!(
+                samples > 0,
+                "Eviction policies need to sample at least 1 bucket"
+            );
+        }
+        self.stamps = match eviction {
+            EvictionPolicy::Lru { .. } | EvictionPolicy::Lfu { .. } => {
+                (0..self.buckets()).map(|_| Cell::new(0)).collect()
+            }
+            EvictionPolicy::Probe | EvictionPolicy::Clock => Vec::new(),
+        };
+        self.eviction = eviction;
+        self.hand = 0;
+        self.tick.set(0);
+    }
+
     /// Deallocates the table without dropping any entries.
     #[inline]
     unsafe fn free_buckets(&mut self) {
         let (layout, ctrl_offset) =
             calculate_layout::<T>(self.buckets()).unwrap_or_else(|| hint::unreachable_unchecked());
         dealloc(self.ctrl.as_ptr().sub(ctrl_offset), layout);
+        dealloc(self.meta.as_ptr(), meta_layout(self.buckets()));
+    }
+
+    /// Returns the number of bytes allocated by a table with room for at
+    /// least `capacity` elements, or `None` if the size overflows.
+    pub fn allocation_size(capacity: usize) -> Option<usize> {
+        let buckets = capacity_to_buckets(capacity)?;
+        let (layout, _) = calculate_layout::<T>(buckets)?;
+        layout.size().checked_add(meta_layout(buckets).size())
+    }
+
+    /// Moves all elements into a new table with room for at least `capacity`
+    /// elements.
+    ///
+    /// Elements keep their meta bytes and access history, so modified elements
+    /// stay modified. Buckets handed out before the resize are no longer valid.
+    pub fn resize(&mut self, capacity: usize, hasher: impl Fn(&T) -> u64) {
+        debug_assert!(capacity >= self.items);
+        unsafe {
+            let mut new_table =
+                Self::try_with_capacity(capacity, self.mod_factor, Fallibility::Infallible)
+                    .unwrap_or_else(|_| hint::unreachable_unchecked());
+            new_table.set_eviction_policy(self.eviction);
+            new_table.tick.set(self.tick.get());
+            for item in self.iter() {
+                let hash = hasher(item.as_ref());
+                let old_index = self.bucket_index(&item);
+                let index = new_table.find_insert_slot(hash);
+                new_table.set_ctrl(index, h2(hash));
+                new_table.set_meta(index, *self.meta(old_index));
+                if let Some(stamp) = self.stamps.get(old_index) {
+                    new_table.stamps[index].set(stamp.get());
+                }
+                ptr::copy_nonoverlapping(item.as_ptr(), new_table.bucket(index).as_ptr(), 1);
+            }
+            new_table.growth_left -= self.items;
+            new_table.items = self.items;
+            new_table.mod_counter = self.mod_counter;
+
+            // The elements have been moved, so only free the old allocation
+            mem::swap(self, &mut new_table);
+            if !new_table.is_empty_singleton() {
+                new_table.free_buckets();
+            }
+            mem::forget(new_table);
+        }
+    }
+
+    /// Drops SAFE elements picked by the eviction policy until the remaining ones
+    /// fit in a table with room for `capacity` elements, then moves them there.
+    ///
+    /// Modified elements are kept, so the caller should write them back first.
+    pub fn shrink(&mut self, capacity: usize, hasher: impl Fn(&T) -> u64) {
+        let fits = capacity_to_buckets(capacity)
+            .map(|buckets| bucket_mask_to_capacity(buckets - 1))
+            .unwrap_or(0);
+        // Vary where victims are looked for, as if for incoming keys
+        let mut seed: u64 = 0;
+        while self.items > fits {
+            seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let index = match self.find_safe_bucket(seed) {
+                Some(index) => index,
+                None => break,
+            };
+            unsafe {
+                self.bucket(index).drop();
+                self.erase_by_index(index);
+                self.set_meta(index, SAFE);
+            }
+        }
+        self.resize(usize::max(capacity, self.items), hasher);
     }
 
     /// Returns pointer to one past last element of data table.
@@ -467,10 +601,54 @@ impl<T> RawTable<T> {
 
     /// Returns the index of a bucket from a `Bucket`.
     #[inline]
-    pub unsafe fn _bucket_index(&self, bucket: &Bucket<T>) -> usize {
+    pub unsafe fn bucket_index(&self, bucket: &Bucket<T>) -> usize {
         bucket.to_base_index(self.data_end())
     }
 
+    /// Returns whether the bucket at the given index holds an element.
+    #[inline]
+    pub unsafe fn is_bucket_full(&self, index: usize) -> bool {
+        is_full(*self.ctrl(index))
+    }
+
+    /// Returns whether the bucket at the given index is marked as modified.
+    #[inline]
+    pub unsafe fn is_bucket_modified(&self, index: usize) -> bool {
+        is_modified(*self.meta(index))
+    }
+
+    /// Returns how hot the bucket at the given index is, higher meaning hotter.
+    ///
+    /// This is the last access time (LRU) or access count (LFU) of the bucket,
+    /// or whether it has been touched for the other eviction policies.
+    #[inline]
+    pub unsafe fn heat(&self, index: usize) -> u64 {
+        match self.stamps.get(index) {
+            Some(stamp) => stamp.get(),
+            None => (*self.meta(index) & TOUCHED != 0) as u64,
+        }
+    }
+
+    /// Marks the bucket at the given index as modified and touched.
+    #[inline]
+    pub unsafe fn touch(&mut self, index: usize) {
+        // If the meta was safe, then increase modification
+        // counter as we are setting the meta to MODIFIED_TOUCHED.
+        if is_safe(*self.meta(index)) {
+            self.mod_counter += 1;
+        }
+        self.set_meta(index, MODIFIED_TOUCHED);
+    }
+
+    /// Marks the bucket at the given index as in sync with the backing store.
+    #[inline]
+    pub unsafe fn mark_safe(&mut self, index: usize) {
+        if is_modified(*self.meta(index)) {
+            self.mod_counter -= 1;
+        }
+        self.set_meta(index, SAFE_TOUCHED);
+    }
+
     /// Returns a pointer to a control byte.
     #[inline]
     unsafe fn ctrl(&self, index: usize) -> *mut u8 {
@@ -606,23 +784,50 @@ impl<T> RawTable<T> {
         unreachable!();
     }
 
-    /// Searches for an index that is suitable to "erase"
+    /// Erases a SAFE bucket picked by the eviction policy
     ///
-    /// There must always be at least 1 SAFE bucket in the table
+    /// Nothing is erased if there is no SAFE bucket to be found.
     #[inline]
     fn clear_safe_bucket(&mut self, hash: u64) {
+        unsafe {
+            let index = match self.find_safe_bucket(hash) {
+                Some(index) => index,
+                None => return,
+            };
+            self.bucket(index).drop();
+            self.erase_by_index(index);
+            // Set its meta byte to SAFE in case it was SAFE_TOUCHED
+            self.set_meta(index, SAFE);
+        }
+    }
+
+    /// Picks a SAFE bucket according to the eviction policy
+    #[inline]
+    fn find_safe_bucket(&mut self, hash: u64) -> Option<usize> {
+        match self.eviction {
+            EvictionPolicy::Probe => self.probe_safe_bucket(hash),
+            _ => unsafe { self.select_bucket(hash, false) },
+        }
+    }
+
+    /// Searches for an index that is suitable to "erase" along the probe sequence of `hash`
+    #[inline]
+    fn probe_safe_bucket(&self, hash: u64) -> Option<usize> {
         for pos in self.probe_seq(hash) {
             unsafe {
                 // In the best case we find a bucket in the first group
                 // as the insert after will be jumping to the same group.
                 let group = Group::load(self.meta(pos));
+                // Empty buckets carry a SAFE meta byte as well,
+                // so only consider buckets that hold an element.
+                let full = Group::load(self.ctrl(pos)).match_full();
 
                 // First attempt to match on SAFE meta byte within the group.
                 // Otherwise, fall back to SAFE_TOUCHED.
                 let bit_opt = {
-                    let safe = group.match_byte(SAFE).lowest_set_bit();
+                    let safe = BitMask(group.match_byte(SAFE).0 & full.0).lowest_set_bit();
                     if safe.is_none() {
-                        group.match_byte(SAFE_TOUCHED).lowest_set_bit()
+                        BitMask(group.match_byte(SAFE_TOUCHED).0 & full.0).lowest_set_bit()
                     } else {
                         safe
                     }
@@ -631,34 +836,123 @@ impl<T> RawTable<T> {
                 if let Some(bit) = bit_opt {
                     let result = (pos + bit) & self.bucket_mask;
 
-                    let index = {
-                        if unlikely(is_modified(*self.meta(result))) {
-                            debug_assert!(self.bucket_mask < Group::WIDTH);
-                            debug_assert_ne!(pos, 0);
+                    if unlikely(is_modified(*self.meta(result))) {
+                        debug_assert!(self.bucket_mask < Group::WIDTH);
+                        debug_assert_ne!(pos, 0);
+                        return Some(
                             Group::load_aligned(self.meta(0))
                                 .match_empty_or_deleted()
-                                .lowest_set_bit_nonzero()
-                        } else {
-                            result
-                        }
-                    };
-                    self.erase_by_index(index);
-                    // Set its meta byte to SAFE in case it was SAFE_TOUCHED
-                    self.set_meta(index, SAFE);
-                    // We are done, return...
-                    return;
+                                .lowest_set_bit_nonzero(),
+                        );
+                    } else {
+                        return Some(result);
+                    }
                 }
             }
         }
+        None
     }
 
-    /// Searches for a modified bucket to evict
+    /// Picks a full bucket that is either modified or safe according to
+    /// [EvictionPolicy::Clock] or one of the sampling policies
+    ///
+    /// Returns `None` if there is no such bucket.
+    unsafe fn select_bucket(&mut self, hash: u64, modified: bool) -> Option<usize> {
+        let candidate = |table: &Self, index: usize| {
+            is_full(*table.ctrl(index)) && is_modified(*table.meta(index)) == modified
+        };
+        match self.eviction {
+            EvictionPolicy::Clock => {
+                // Every candidate is visited at most twice, as the first
+                // visit clears its TOUCHED bit.
+                for _ in 0..self.buckets() * 2 {
+                    let index = self.hand;
+                    self.hand = (self.hand + 1) & self.bucket_mask;
+                    if !candidate(self, index) {
+                        continue;
+                    }
+                    let meta = *self.meta(index);
+                    if meta & TOUCHED != 0 {
+                        self.set_meta(index, meta & !TOUCHED);
+                    } else {
+                        return Some(index);
+                    }
+                }
+                None
+            }
+            EvictionPolicy::Lru { samples } | EvictionPolicy::Lfu { samples } => {
+                // Sample consecutive buckets from the home bucket of `hash`,
+                // which is as good as random for a decent hasher.
+                let start = h1(hash) & self.bucket_mask;
+                let mut victim: Option<(usize, u64)> = None;
+                let mut sampled = 0;
+                for offset in 0..self.buckets() {
+                    let index = (start + offset) & self.bucket_mask;
+                    if !candidate(self, index) {
+                        continue;
+                    }
+                    let stamp = self.stamps[index].get();
+                    match victim {
+                        Some((_, oldest)) if oldest <= stamp => (),
+                        _ => victim = Some((index, stamp)),
+                    }
+                    sampled += 1;
+                    if sampled == samples {
+                        break;
+                    }
+                }
+                victim.map(|(index, _)| index)
+            }
+            EvictionPolicy::Probe => unreachable!(),
+        }
+    }
+
+    /// Records an access to the bucket at the given index for the sampling policies
+    #[inline]
+    fn record_access(&self, index: usize, inserted: bool) {
+        let stamp = match self.stamps.get(index) {
+            Some(stamp) => stamp,
+            None => return,
+        };
+        let tick = self.tick.get() + 1;
+        self.tick.set(tick);
+        match self.eviction {
+            EvictionPolicy::Lru { .. } => stamp.set(tick),
+            EvictionPolicy::Lfu { .. } => {
+                let count = if inserted { 1 } else { stamp.get() + 1 };
+                stamp.set(count);
+                if unlikely(tick >= self.buckets() as u64 * LFU_DECAY_PERIOD) {
+                    for stamp in self.stamps.iter() {
+                        stamp.set(stamp.get() / 2);
+                    }
+                    self.tick.set(0);
+                }
+            }
+            EvictionPolicy::Probe | EvictionPolicy::Clock => (),
+        }
+    }
+
+    /// Picks a modified bucket to evict according to the eviction policy and returns its index
+    ///
+    /// The bucket stays modified until [RawTable::release_mod_bucket] is called,
+    /// e.g., once it has been written to the backing store.
     ///
     /// Safety: Should only be called when there is at least 1 modified bucket in the table
     #[inline]
-    #[allow(unused_assignments)]
-    pub(crate) unsafe fn evict_mod_bucket(&mut self, hash: u64) -> Bucket<T> {
+    pub(crate) unsafe fn find_mod_bucket(&mut self, hash: u64) -> usize {
         debug_assert_ne!(self.mod_counter, 0);
+        match self.eviction {
+            EvictionPolicy::Probe => self.probe_mod_bucket(hash),
+            _ => self
+                .select_bucket(hash, true)
+                .unwrap_or_else(|| unreachable!()),
+        }
+    }
+
+    /// Searches for a modified bucket along the probe sequence of `hash`
+    #[inline]
+    #[allow(unused_assignments)]
+    unsafe fn probe_mod_bucket(&self, hash: u64) -> usize {
         for pos in self.probe_seq(hash) {
             let group = Group::load(self.meta(pos));
 
@@ -697,18 +991,7 @@ impl<T> RawTable<T> {
                     }
                 };
 
-                let bucket = self.bucket(index);
-
-                if self.growth_left == 0 {
-                  // If there is no space left, then actually "erase" it.
-                  self.erase_by_index(index);
-                }
-
-                // Set bucket to safe
-                self.set_meta(index, SAFE);
-                self.mod_counter -= 1;
-
-                return bucket;
+                return index;
             }
         }
 
@@ -716,11 +999,39 @@ impl<T> RawTable<T> {
         unreachable!();
     }
 
+    /// Marks an evicted bucket as safe, erasing it if there is no space left
+    #[inline]
+    pub(crate) unsafe fn release_mod_bucket(&mut self, index: usize) {
+        if self.growth_left == 0 {
+            // If there is no space left, then actually "erase" it.
+            self.bucket(index).drop();
+            self.erase_by_index(index);
+        }
+
+        // Set bucket to safe
+        self.set_meta(index, SAFE);
+        self.mod_counter -= 1;
+    }
+
     /// Inserts a new element into the table.
     ///
     /// This does not check if the given element already exists in the table.
     #[inline]
     pub fn insert(&mut self, hash: u64, value: T) -> Bucket<T> {
+        self.insert_with_meta(hash, value, MODIFIED)
+    }
+
+    /// Inserts a new element into the table without marking it as modified.
+    ///
+    /// Meant for elements that are already in sync with the backing store.
+    /// This does not check if the given element already exists in the table.
+    #[inline]
+    pub fn insert_safe(&mut self, hash: u64, value: T) -> Bucket<T> {
+        self.insert_with_meta(hash, value, SAFE)
+    }
+
+    #[inline]
+    fn insert_with_meta(&mut self, hash: u64, value: T, meta: u8) -> Bucket<T> {
         unsafe {
             if unlikely(self.growth_left == 0) {
                 self.clear_safe_bucket(hash);
@@ -734,10 +1045,13 @@ impl<T> RawTable<T> {
                 .growth_left
                 .saturating_sub(special_is_empty(ctrl) as usize);
             self.set_ctrl(index, h2(hash));
-            self.set_meta(index, MODIFIED);
+            self.set_meta(index, meta);
+            self.record_access(index, true);
             bucket.write(value);
             self.items += 1;
-            self.mod_counter += 1;
+            if is_modified(meta) {
+                self.mod_counter += 1;
+            }
             bucket
         }
     }
@@ -757,13 +1071,8 @@ impl<T> RawTable<T> {
                     let index = (pos + bit) & self.bucket_mask;
                     let bucket = self.bucket(index);
                     if likely(eq(bucket.as_ref())) {
-                        // If the meta was safe, then increase modification
-                        // counter as we are setting the meta to MODIFIED_TOUCHED.
-                        if is_safe(*self.meta(index)) {
-                            self.mod_counter += 1;
-                        }
-
-                        self.set_meta(index, MODIFIED_TOUCHED);
+                        self.touch(index);
+                        self.record_access(index, false);
                         return Some(bucket);
                     }
                 }
@@ -783,9 +1092,8 @@ impl<T> RawTable<T> {
                     let index = (pos + bit) & self.bucket_mask;
                     let bucket = self.bucket(index);
                     if likely(eq(bucket.as_ref())) {
-                        if is_safe(*self.meta(index)) {
-                            self.set_meta(index, SAFE_TOUCHED);
-                        }
+                        self.set_meta(index, *self.meta(index) | TOUCHED);
+                        self.record_access(index, false);
                         return Some(bucket);
                     }
                 }
@@ -794,6 +1102,27 @@ impl<T> RawTable<T> {
         return None;
     }
 
+    /// Searches for an element in the table without recording an access.
+    ///
+    /// Unlike [RawTable::find], the lookup leaves the meta byte and access stamp
+    /// of the bucket as they are, so it does not affect which entries are evicted.
+    #[inline]
+    pub fn peek(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> Option<Bucket<T>> {
+        unsafe {
+            for pos in self.probe_seq(hash) {
+                let group = Group::load(self.ctrl(pos));
+                for bit in group.match_byte(h2(hash)) {
+                    let index = (pos + bit) & self.bucket_mask;
+                    let bucket = self.bucket(index);
+                    if likely(eq(bucket.as_ref())) {
+                        return Some(bucket);
+                    }
+                }
+            }
+        }
+        None
+    }
+
     /// Returns the number of elements the map can hold without reallocating.
     ///
     /// This number is a lower bound; the table might be able to hold
@@ -803,6 +1132,12 @@ impl<T> RawTable<T> {
         self.items + self.growth_left
     }
 
+    /// Returns whether no more elements fit without dropping others.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.growth_left == 0
+    }
+
     /// Returns the number of elements in the table.
     #[inline]
     pub fn len(&self) -> usize {
@@ -841,21 +1176,25 @@ impl<T> RawTable<T> {
     }
 
     /// Returns an iterator over every element in the table that has a meta byte set as MODIFIED.
-    /// Note that this iterator will reset every MODIFIED byte to SAFE.
+    ///
+    /// Elements stay modified until [RawTable::clear_modified] is called for them.
     #[inline]
-    pub unsafe fn iter_modified(&mut self) -> ModifiedIterator<T> {
+    pub unsafe fn iter_modified(&self) -> ModifiedIterator<T> {
         let data = Bucket::from_base_index(self.data_end(), 0);
-        self.mod_counter = 0;
         ModifiedIterator {
-            iter: RawIterModified::new(
-                self.ctrl.as_ptr(),
-                self.meta.as_ptr(),
-                data,
-                self.buckets(),
-            ),
+            iter: RawIterModified::new(self.meta.as_ptr(), data, self.buckets()),
         }
     }
 
+    /// Marks a modified bucket as in sync with the backing store, keeping its TOUCHED bit.
+    #[inline]
+    pub unsafe fn clear_modified(&mut self, index: usize) {
+        let meta = *self.meta(index);
+        debug_assert!(is_modified(meta));
+        self.set_meta(index, SAFE | (meta & TOUCHED));
+        self.mod_counter -= 1;
+    }
+
     /// Returns an iterator over every element in the table. It is up to
     /// the caller to ensure that the `RawTable` outlives the `RawIter`.
     /// Because we cannot make the `next` method unsafe on the `RawIter`
@@ -915,9 +1254,6 @@ pub(crate) struct RawIterModified<T> {
     // Pointer to the buckets for the current group.
     data: Bucket<T>,
 
-    ctrl: NonNull<u8>,
-    meta: NonNull<u8>,
-
     // Pointer to the next group of meta bytes
     // Must be aligned to the group size.
     next_meta: *const u8,
@@ -927,9 +1263,9 @@ pub(crate) struct RawIterModified<T> {
 impl<T> RawIterModified<T> {
     /// Returns a `RawIterModified` covering modified buckets of the table
     ///
-    /// The control & meta byte address must be aligned to the group size.
+    /// The meta byte address must be aligned to the group size.
     #[inline]
-    unsafe fn new(ctrl: *const u8, meta: *const u8, data: Bucket<T>, len: usize) -> Self {
+    unsafe fn new(meta: *const u8, data: Bucket<T>, len: usize) -> Self {
         debug_assert_ne!(len, 0);
         debug_assert_eq!(meta as usize % Group::WIDTH, 0);
         let meta_end = meta.add(len);
@@ -937,14 +1273,10 @@ impl<T> RawIterModified<T> {
         // Load the first group and advance meta to point to the next group
         let current_group = Group::load_aligned(meta).match_modified();
         let next_meta = meta.add(Group::WIDTH);
-        let ctrl = NonNull::new_unchecked(ctrl as *mut u8);
-        let meta = NonNull::new_unchecked(meta as *mut u8);
 
         Self {
             current_group,
             data,
-            ctrl,
-            meta,
             next_meta,
             meta_end,
         }
@@ -956,8 +1288,6 @@ impl<T> Clone for RawIterModified<T> {
     fn clone(&self) -> Self {
         Self {
             data: self.data.clone(),
-            ctrl: self.ctrl,
-            meta: self.meta,
             next_meta: self.next_meta,
             current_group: self.current_group,
             meta_end: self.meta_end,
@@ -974,13 +1304,7 @@ impl<T> Iterator for RawIterModified<T> {
             loop {
                 if let Some(index) = self.current_group.lowest_set_bit() {
                     self.current_group = self.current_group.remove_lowest_bit();
-                    let bucket = self.data.next_n(index);
-                    // Get index for this bucket
-                    let bucket_index =
-                        bucket.to_base_index(NonNull::new_unchecked(self.ctrl.as_ptr() as *mut T));
-                    // Set the meta byte of the index to SAFE
-                    std::ptr::write(self.meta.as_ptr().add(bucket_index), SAFE);
-                    return Some(bucket);
+                    return Some(self.data.next_n(index));
                 }
 
                 if self.next_meta >= self.meta_end {
@@ -1227,3 +1551,56 @@ impl<T> Iterator for RawIter<T> {
 
 impl<T> ExactSizeIterator for RawIter<T> {}
 impl<T> FusedIterator for RawIter<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Element that counts how many times it has been dropped
+    struct DropCounter(Rc<Cell<usize>>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[inline]
+    fn spread(i: u64) -> u64 {
+        i.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    }
+
+    #[test]
+    fn evicted_elements_are_dropped_test() {
+        let drops = Rc::new(Cell::new(0));
+        let mut inserted = 0;
+
+        // SAFE elements erased to make room for new ones
+        let mut table: RawTable<DropCounter> = RawTable::with_capacity(16, 0.5);
+        for i in 0..64u64 {
+            table.insert_safe(spread(i), DropCounter(drops.clone()));
+            inserted += 1;
+        }
+        assert_eq!(drops.get(), inserted - table.len());
+        drop(table);
+        assert_eq!(drops.get(), inserted);
+
+        // A released modified element is erased once the table is full
+        let mut table: RawTable<DropCounter> = RawTable::with_capacity(16, 0.9);
+        let mut i = 0;
+        while !table.is_full() {
+            table.insert(spread(i), DropCounter(drops.clone()));
+            inserted += 1;
+            i += 1;
+        }
+        unsafe {
+            let index = table.find_mod_bucket(spread(0));
+            table.release_mod_bucket(index);
+        }
+        assert_eq!(drops.get(), inserted - table.len());
+        drop(table);
+        assert_eq!(drops.get(), inserted);
+    }
+}
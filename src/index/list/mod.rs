@@ -0,0 +1,504 @@
+use crate::data::{key_prefix, Key, OrderedKey, Value};
+use crate::error::*;
+use crate::index::hash::DefaultHashBuilder;
+use crate::index::recency::Recency;
+use crate::index::{IndexOps, ListOps, WriteMode};
+use crate::raw_store::RawStore;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Number of elements fetched from the RawStore per refill of a list scan
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// In-memory state of the list of a single key
+///
+/// Elements of a list are numbered by a sequence that grows with every append.
+/// Only the tail of the list is kept in memory, the rest lives in the RawStore.
+struct List<V> {
+    /// Sequence of the first element in the list
+    head: u64,
+    /// Sequence of the next appended element
+    next: u64,
+    /// Latest elements, the last one having sequence `next - 1`
+    tail: Vec<V>,
+    /// Number of elements at the front of the tail that are in the RawStore
+    persisted: Cell<usize>,
+}
+
+impl<V> List<V> {
+    #[inline]
+    fn new(head: u64, next: u64) -> Self {
+        List {
+            head,
+            next,
+            tail: Vec::new(),
+            persisted: Cell::new(0),
+        }
+    }
+
+    /// Sequence of the first element of the tail
+    #[inline]
+    fn tail_start(&self) -> u64 {
+        self.next - self.tail.len() as u64
+    }
+
+    /// Whether the tail has elements that are not in the RawStore
+    #[inline]
+    fn is_modified(&self) -> bool {
+        self.persisted.get() < self.tail.len()
+    }
+
+    /// Drops the elements of the tail that are already in the RawStore
+    #[inline]
+    fn trim(&mut self) {
+        let persisted = self.persisted.replace(0);
+        self.tail.drain(..persisted);
+    }
+
+    /// Encodes the sequence bounds of the list
+    #[inline]
+    fn encode_meta(&self) -> Vec<u8> {
+        let mut meta = Vec::with_capacity(16);
+        meta.extend_from_slice(&self.head.to_be_bytes());
+        meta.extend_from_slice(&self.next.to_be_bytes());
+        meta
+    }
+}
+
+/// An Index that maps each key to an append-only list of values
+///
+/// Appends are persisted as deltas: each element is stored under its own RawStore
+/// key made up of the list key followed by the sequence of the element, so
+/// appending never re-encodes the rest of the list. A small metadata record per
+/// key keeps track of the sequence bounds. Unpersisted appends are buffered in
+/// an in-memory tail for up to `capacity` keys.
+pub struct ListIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+{
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
+    /// In-memory lists by key
+    lists: HashMap<K, List<V>, DefaultHashBuilder>,
+    /// Order in which the keys in memory were last used
+    recency: Recency<K>,
+    /// Max number of keys held in memory
+    capacity: usize,
+    /// Write Mode
+    mode: WriteMode,
+    /// The RawStore layer where things are persisted
+    raw_store: Rc<RefCell<RawStore>>,
+}
+
+impl<K, V> ListIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+{
+    /// Creates a ListIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
+    #[inline]
+    pub fn new<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::default(), raw_store)
+    }
+
+    /// Creates a ListIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::Cow, raw_store)
+    }
+
+    fn setup<I>(
+        namespace: I,
+        capacity: usize,
+        mode: WriteMode,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> ListIndex<K, V>
+    where
+        I: Into<Vec<u8>>,
+    {
+        assert!(capacity > 0, "ListIndex needs room for at least one key");
+        ListIndex {
+            namespace: namespace.into(),
+            lists: HashMap::default(),
+            recency: Recency::new(),
+            capacity,
+            mode,
+            raw_store,
+        }
+    }
+
+    /// Returns the number of keys held in memory
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.lists.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of elements in the list of a key
+    pub fn list_len(&mut self, key: &K) -> Result<usize> {
+        let list = self.load(key)?;
+        Ok((list.next - list.head) as usize)
+    }
+
+    /// Brings the list of a key into memory, reading its bounds from the RawStore
+    fn load(&mut self, key: &K) -> Result<&mut List<V>> {
+        if !self.lists.contains_key(key) {
+            let meta = self
+                .raw_store
+                .borrow()
+                .get_bytes(&self.namespace, &key_prefix(key)?)?;
+            let list = match meta {
+                Some(meta) => decode_meta(&meta)?,
+                None => List::new(0, 0),
+            };
+            self.make_room()?;
+            self.lists.insert(key.clone(), list);
+        }
+        self.recency.touch(key);
+        Ok(self.lists.get_mut(key).expect("Unexpected error"))
+    }
+
+    /// Flushes and drops the least recently used lists until there is room for one more
+    fn make_room(&mut self) -> Result<()> {
+        while self.lists.len() >= self.capacity {
+            let key = match self.recency.oldest() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            if let Some(list) = self.lists.get(&key) {
+                self.write(&key, list)?;
+            }
+            self.lists.remove(&key);
+            self.recency.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Writes the unpersisted part of a tail together with the list bounds
+    fn write(&self, key: &K, list: &List<V>) -> Result<()> {
+        if !list.is_modified() {
+            return Ok(());
+        }
+        let prefix = key_prefix(key)?;
+        let start = list.tail_start();
+        let mut records = Vec::new();
+        for (offset, value) in list.tail.iter().enumerate().skip(list.persisted.get()) {
+            let seq = start + offset as u64;
+            records.push((element_key(&prefix, seq), Some(value.into_raw()?)));
+        }
+        records.push((prefix, Some(list.encode_meta())));
+        self.raw_store
+            .borrow_mut()
+            .write_bytes_batch(&self.namespace, records)?;
+        list.persisted.set(list.tail.len());
+        Ok(())
+    }
+
+    /// Reads the persisted elements of a list in sequence order
+    fn read(&self, key: &K) -> Result<Vec<V>> {
+        let mut scan = ElementScan::new(self.raw_store.clone(), self.namespace.clone(), key)?;
+        if let Some(list) = self.lists.get(key) {
+            scan.end = Some(list.tail_start());
+        }
+        let mut values = Vec::new();
+        while let Some(value) = scan.next_value()? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+/// Returns the RawStore key of the element with sequence `seq`
+#[inline]
+fn element_key(prefix: &[u8], seq: u64) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    seq.encode_ordered(&mut key);
+    key
+}
+
+#[inline]
+fn decode_meta<V>(meta: &[u8]) -> Result<List<V>> {
+    if meta.len() != 16 {
+        return Err(BrittMarieError::Serde(format!(
+            "expected 16 bytes of list metadata, found {}",
+            meta.len()
+        )));
+    }
+    let head = u64::decode_ordered(&meta[..8])?;
+    let next = u64::decode_ordered(&meta[8..])?;
+    Ok(List::new(head, next))
+}
+
+impl<K, V> IndexOps for ListIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+{
+    fn persist(&self) -> Result<()> {
+        if self.mode.is_lazy() {
+            for (key, list) in self.lists.iter() {
+                self.write(key, list)?;
+            }
+        }
+        // Else just ignore as COW copies on each append
+        Ok(())
+    }
+}
+
+impl<K, V> ListOps<K, V> for ListIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value,
+{
+    fn try_append(&mut self, key: K, value: V) -> Result<()> {
+        let cow = self.mode.is_cow();
+        let list = self.load(&key)?;
+        list.trim();
+        list.tail.push(value);
+        list.next += 1;
+        if cow {
+            let list = &self.lists[&key];
+            self.write(&key, list)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn iter(&self, key: &K) -> Iter<'_, V> {
+        let mut disk = ElementScan::new(self.raw_store.clone(), self.namespace.clone(), key)
+            .expect("Unexpected error");
+        let tail = match self.lists.get(key) {
+            Some(list) => {
+                disk.end = Some(list.tail_start());
+                list.tail.iter()
+            }
+            None => [].iter(),
+        };
+        Iter { disk, tail }
+    }
+
+    fn try_clear(&mut self, key: &K) -> Result<()> {
+        let list = self.load(key)?;
+        let (head, next) = (list.head, list.next);
+        let prefix = key_prefix(key)?;
+        let mut records: Vec<(Vec<u8>, Option<Vec<u8>>)> = (head..next)
+            .map(|seq| (element_key(&prefix, seq), None))
+            .collect();
+        records.push((prefix, None));
+        self.raw_store
+            .borrow_mut()
+            .write_bytes_batch(&self.namespace, records)?;
+        self.lists.remove(key);
+        self.recency.remove(key);
+        Ok(())
+    }
+
+    fn try_drain(&mut self, key: &K) -> Result<Vec<V>> {
+        let mut values = self.read(key)?;
+        let tail = match self.lists.get_mut(key) {
+            Some(list) => std::mem::take(&mut list.tail),
+            None => Vec::new(),
+        };
+        self.try_clear(key)?;
+        values.extend(tail);
+        Ok(values)
+    }
+}
+
+/// Iterator over the list of a key in append order
+///
+/// Chains the elements in the RawStore with the in-memory tail of the list.
+pub struct Iter<'a, V>
+where
+    V: Value,
+{
+    disk: ElementScan<V>,
+    tail: std::slice::Iter<'a, V>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V>
+where
+    V: Value,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.disk.next_value().expect("Unexpected error") {
+            Some(value) => Some(value),
+            None => self.tail.next().cloned(),
+        }
+    }
+}
+
+/// Scan over the elements of a list that are in the RawStore
+///
+/// Elements are fetched in batches so that the RawStore is only borrowed
+/// while a batch is read.
+struct ElementScan<V> {
+    raw_store: Rc<RefCell<RawStore>>,
+    namespace: Vec<u8>,
+    prefix: Vec<u8>,
+    /// Sequence to continue from, `None` once the scan is exhausted
+    next: Option<u64>,
+    /// Sequence at which the scan stops, if any
+    end: Option<u64>,
+    buffer: VecDeque<Vec<u8>>,
+    _marker: PhantomData<V>,
+}
+
+impl<V> ElementScan<V>
+where
+    V: Value,
+{
+    fn new<K: Key>(raw_store: Rc<RefCell<RawStore>>, namespace: Vec<u8>, key: &K) -> Result<Self> {
+        Ok(ElementScan {
+            raw_store,
+            namespace,
+            prefix: key_prefix(key)?,
+            next: Some(0),
+            end: None,
+            buffer: VecDeque::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    fn refill(&mut self) -> Result<()> {
+        let from = match self.next.take() {
+            Some(from) => from,
+            None => return Ok(()),
+        };
+        let records = self.raw_store.borrow().scan(
+            &self.namespace,
+            &element_key(&self.prefix, from),
+            SCAN_BATCH_SIZE,
+        )?;
+        let full = records.len() == SCAN_BATCH_SIZE;
+        for (key, raw) in records {
+            if !key.starts_with(&self.prefix) {
+                return Ok(());
+            }
+            let seq = u64::decode_ordered(&key[self.prefix.len()..])?;
+            match self.end {
+                Some(end) if seq >= end => return Ok(()),
+                _ => (),
+            }
+            self.buffer.push_back(raw);
+            if full {
+                self.next = Some(seq + 1);
+            }
+        }
+        Ok(())
+    }
+
+    fn next_value(&mut self) -> Result<Option<V>> {
+        if self.buffer.is_empty() {
+            self.refill()?;
+        }
+        match self.buffer.pop_front() {
+            Some(raw) => V::from_raw(&raw).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn basic_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: ListIndex<u64, u64> = ListIndex::new("buffers", 4, raw_store.clone());
+        // Spread the lists over memory and disk
+        for i in 0..300 {
+            index.append(i % 10, i);
+        }
+        assert!(index.len() <= 4);
+        for key in 0..10 {
+            let expected: Vec<u64> = (0..300).filter(|i| i % 10 == key).collect();
+            assert_eq!(index.iter(&key).collect::<Vec<_>>(), expected);
+            assert_eq!(index.list_len(&key).unwrap(), 30);
+        }
+        assert!(index.persist().is_ok());
+        index.append(3, 1000);
+        assert_eq!(index.iter(&3).last(), Some(1000));
+
+        let drained = index.drain(&3);
+        assert_eq!(drained.len(), 31);
+        assert_eq!(drained[0], 3);
+        assert_eq!(index.iter(&3).count(), 0);
+        index.clear(&4);
+        assert_eq!(index.iter(&4).count(), 0);
+        assert_eq!(index.list_len(&4).unwrap(), 0);
+        assert_eq!(index.iter(&5).count(), 30);
+
+        // Lists are picked up from the RawStore by a fresh index
+        index.append(5, 5000);
+        index.persist().unwrap();
+        let mut other: ListIndex<u64, u64> = ListIndex::new("buffers", 4, raw_store);
+        assert_eq!(other.iter(&5).count(), 31);
+        assert_eq!(other.iter(&3).count(), 0);
+        other.append(5, 6000);
+        assert_eq!(other.iter(&5).last(), Some(6000));
+        assert_eq!(other.list_len(&5).unwrap(), 32);
+    }
+
+    #[test]
+    fn eviction_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: ListIndex<u64, u64> = ListIndex::new("recent", 2, raw_store);
+        index.append(1, 10);
+        index.append(2, 20);
+        // Using key 1 again makes key 2 the least recently used one
+        index.append(1, 11);
+        index.append(3, 30);
+        assert!(index.lists.contains_key(&1));
+        assert!(!index.lists.contains_key(&2));
+        assert_eq!(index.iter(&2).collect::<Vec<_>>(), vec![20]);
+    }
+
+    #[test]
+    fn cow_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: ListIndex<String, u64> = ListIndex::cow("joins", 2, raw_store.clone());
+        index.append(String::from("a"), 1);
+        index.append(String::from("a"), 2);
+        index.append(String::from("ab"), 3);
+
+        // Appends went straight to the RawStore
+        let other: ListIndex<String, u64> = ListIndex::new("joins", 2, raw_store);
+        assert_eq!(
+            other.iter(&String::from("a")).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(other.iter(&String::from("ab")).collect::<Vec<_>>(), vec![3]);
+    }
+}
@@ -1,8 +1,17 @@
+pub mod aggregate;
 pub mod hash;
+pub mod list;
+pub mod multimap;
+pub mod ordered;
+mod recency;
+pub mod set;
+pub mod timer;
 pub mod value;
+pub mod window;
 
-use crate::data::{Key, Value};
+use crate::data::{Key, OrderedKey, Value};
 use crate::error::*;
+use std::ops::RangeBounds;
 
 /// Write Modes that Britt-Marie provide
 #[derive(PartialEq)]
@@ -14,7 +23,9 @@ pub enum WriteMode {
     /// Copy-on-Write
     ///
     /// Each new write will be logged. This mode is useful if you want to be able
-    /// to track all updates to a specific object.
+    /// to track all updates to a specific object, see [ValueIndex::history].
+    ///
+    /// [ValueIndex::history]: crate::ValueIndex::history
     Cow,
 }
 impl WriteMode {
@@ -34,20 +45,216 @@ impl Default for WriteMode {
     }
 }
 
+/// Decode Modes for values that are read back from the RawStore
+#[derive(PartialEq)]
+pub enum DecodeMode {
+    /// Eager
+    ///
+    /// Values are decoded as soon as they are fetched from the RawStore.
+    Eager,
+    /// Lazy
+    ///
+    /// Fetched values are kept in their encoded form until the first typed access.
+    /// Entries that are evicted before being modified are dropped without re-serialization.
+    Lazy,
+}
+impl DecodeMode {
+    #[inline(always)]
+    pub fn is_lazy(&self) -> bool {
+        *self == DecodeMode::Lazy
+    }
+}
+
+impl Default for DecodeMode {
+    fn default() -> Self {
+        DecodeMode::Eager
+    }
+}
+
+/// Resize Policies for the RawTable of a HashIndex
+#[derive(PartialEq)]
+pub enum ResizePolicy {
+    /// Fixed
+    ///
+    /// The RawTable keeps its initial capacity. Once full, entries that are in
+    /// sync with the RawStore are dropped to make room.
+    Fixed,
+    /// Grow
+    ///
+    /// A full RawTable doubles its capacity as long as its allocation stays within
+    /// `max_bytes`. The budget covers the RawTable itself, not heap memory owned by
+    /// keys and values.
+    Grow { max_bytes: usize },
+}
+
+impl Default for ResizePolicy {
+    fn default() -> Self {
+        ResizePolicy::Fixed
+    }
+}
+
+/// Eviction Policies for the RawTable of a HashIndex
+///
+/// A policy picks both the modified entry that is written back once the
+/// modification threshold is reached and the in-sync entry that is dropped
+/// once the RawTable is full.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum EvictionPolicy {
+    /// Probe
+    ///
+    /// Picks the first candidate along the probe sequence of the incoming key,
+    /// preferring entries that have not been touched.
+    Probe,
+    /// Clock
+    ///
+    /// A clock hand sweeps over the RawTable and gives touched entries a second chance.
+    Clock,
+    /// Approximate LRU
+    ///
+    /// Picks the least recently used entry out of `samples` candidates.
+    Lru { samples: usize },
+    /// Approximate LFU
+    ///
+    /// Picks the least frequently used entry out of `samples` candidates.
+    /// Access counts are halved periodically so that old bursts fade out.
+    Lfu { samples: usize },
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Probe
+    }
+}
+
+/// Window Assigners of a WindowIndex
+///
+/// Timestamps and window bounds share the unit of the event time, e.g., milliseconds.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WindowAssigner {
+    /// Tumbling
+    ///
+    /// Fixed-size windows that do not overlap, each event belongs to exactly one.
+    Tumbling { size: u64 },
+    /// Sliding
+    ///
+    /// Fixed-size windows that start every `slide`, an event belongs to every
+    /// window that covers its timestamp. If `slide` is larger than `size`, events
+    /// between two windows belong to none.
+    Sliding { size: u64, slide: u64 },
+    /// Session
+    ///
+    /// Windows per key that extend as long as events arrive within `gap` of each
+    /// other. Sessions that come to overlap are merged, see [WindowIndex::session].
+    ///
+    /// [WindowIndex::session]: crate::WindowIndex::session
+    Session { gap: u64 },
+}
+
+impl WindowAssigner {
+    #[inline(always)]
+    pub fn is_session(&self) -> bool {
+        matches!(self, WindowAssigner::Session { .. })
+    }
+
+    /// Returns the windows of an event with timestamp `timestamp` in ascending order
+    ///
+    /// A session window only covers the event itself until it is merged.
+    pub fn assign(&self, timestamp: u64) -> Vec<window::Window> {
+        match *self {
+            WindowAssigner::Tumbling { size } => {
+                let start = timestamp - timestamp % size;
+                vec![window::Window::new(start, start.saturating_add(size))]
+            }
+            WindowAssigner::Sliding { size, slide } => {
+                let mut windows = Vec::new();
+                let mut start = timestamp - timestamp % slide;
+                // Windows that end before the event do not cover it. With a slide
+                // larger than the size, that may already be the case for the last one.
+                while start.saturating_add(size) > timestamp {
+                    windows.push(window::Window::new(start, start.saturating_add(size)));
+                    match start.checked_sub(slide) {
+                        Some(prev) => start = prev,
+                        None => break,
+                    }
+                }
+                windows.reverse();
+                windows
+            }
+            WindowAssigner::Session { gap } => {
+                vec![window::Window::new(
+                    timestamp,
+                    timestamp.saturating_add(gap),
+                )]
+            }
+        }
+    }
+}
+
 /// Common Index Operations
 pub trait IndexOps {
     /// This method ensures all non-persisted data gets pushed to the RawStore
     fn persist(&self) -> Result<()>;
+    /// Shrinks the index if the [MemoryManager](crate::MemoryManager) asked it to
+    ///
+    /// Indexes otherwise shrink on their next update. Calling this on every index,
+    /// e.g., through the `enforce_budget` method generated by `#[derive(BrittMarie)]`,
+    /// also reclaims memory from indexes that are no longer updated.
+    fn enforce_budget(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Operations supported by Ordered Indexes
-pub trait OrderedOps: IndexOps {
+pub trait OrderedOps<K, V>: IndexOps
+where
+    K: OrderedKey,
+    V: Value,
+{
     /// Fetch value by key
-    fn get<K: Key, V: Value>(&self, key: &K) -> Option<&V>;
+    fn try_get(&mut self, key: &K) -> Result<Option<&V>>;
     /// Blind insert
-    fn put<K: Key, V: Value>(&mut self, key: &K, value: V);
-    /// Range Scan where returned values are ordered
-    fn range<K: Key + Ord, V: Value>(&mut self, start: &K, end: &K) -> dyn Iterator<Item = V>;
+    fn try_put(&mut self, key: K, value: V) -> Result<()>;
+    /// Read-Modify-Write operation
+    fn try_rmw<F: Sized>(&mut self, key: &K, f: F) -> Result<bool>
+    where
+        F: FnMut(&mut V);
+    /// Range Scan where entries are returned in ascending key order
+    ///
+    /// Panics if the start of the range is greater than its end, and on RawStore
+    /// or decode errors while scanning, see [OrderedOps::try_range].
+    fn range<R>(&mut self, range: R) -> ordered::Range<'_, K, V>
+    where
+        R: RangeBounds<K>;
+
+    /// Same as [OrderedOps::range] but yields RawStore and decode errors
+    #[inline(always)]
+    fn try_range<R>(&mut self, range: R) -> ordered::TryRange<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        ordered::TryRange {
+            inner: self.range(range),
+        }
+    }
+
+    /// Same as [OrderedOps::try_get] but panics on RawStore errors
+    #[inline(always)]
+    fn get(&mut self, key: &K) -> Option<&V> {
+        self.try_get(key).expect("Unexpected error")
+    }
+    /// Same as [OrderedOps::try_put] but panics on RawStore errors
+    #[inline(always)]
+    fn put(&mut self, key: K, value: V) {
+        self.try_put(key, value).expect("Unexpected error")
+    }
+    /// Same as [OrderedOps::try_rmw] but panics on RawStore errors
+    #[inline(always)]
+    fn rmw<F: Sized>(&mut self, key: &K, f: F) -> bool
+    where
+        F: FnMut(&mut V),
+    {
+        self.try_rmw(key, f).expect("Unexpected error")
+    }
 }
 
 /// Operations available for a HashIndex
@@ -57,13 +264,39 @@ where
     V: Value,
 {
     /// Fetch value by key
-    fn get(&self, key: &K) -> Option<&V>;
+    fn try_get(&self, key: &K) -> Result<Option<&V>>;
     /// Blind insert
-    fn put(&mut self, key: K, value: V);
+    fn try_put(&mut self, key: K, value: V) -> Result<()>;
     /// Read-Modify-Write operation
-    fn rmw<F: Sized>(&mut self, key: &K, f: F) -> bool
+    fn try_rmw<F: Sized>(&mut self, key: &K, f: F) -> Result<bool>
     where
         F: FnMut(&mut V);
+    /// Removes a key, returning its value if it existed
+    fn try_remove(&mut self, key: &K) -> Result<Option<V>>;
+
+    /// Same as [HashOps::try_get] but panics on RawStore errors
+    #[inline(always)]
+    fn get(&self, key: &K) -> Option<&V> {
+        self.try_get(key).expect("Unexpected error")
+    }
+    /// Same as [HashOps::try_put] but panics on RawStore errors
+    #[inline(always)]
+    fn put(&mut self, key: K, value: V) {
+        self.try_put(key, value).expect("Unexpected error")
+    }
+    /// Same as [HashOps::try_rmw] but panics on RawStore errors
+    #[inline(always)]
+    fn rmw<F: Sized>(&mut self, key: &K, f: F) -> bool
+    where
+        F: FnMut(&mut V),
+    {
+        self.try_rmw(key, f).expect("Unexpected error")
+    }
+    /// Same as [HashOps::try_remove] but panics on RawStore errors
+    #[inline(always)]
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.try_remove(key).expect("Unexpected error")
+    }
 }
 
 /// Operations available for a ValueIndex
@@ -74,9 +307,55 @@ where
     /// Fetch value
     fn get(&self) -> Option<&V>;
     /// Blind insert
-    fn put(&mut self, value: V);
+    fn try_put(&mut self, value: V) -> Result<()>;
     /// Read-Modify-Write operation
-    fn rmw<F: Sized>(&mut self, f: F) -> bool
+    fn try_rmw<F: Sized>(&mut self, f: F) -> Result<bool>
     where
         F: FnMut(&mut V);
+
+    /// Same as [ValueOps::try_put] but panics on RawStore errors
+    #[inline(always)]
+    fn put(&mut self, value: V) {
+        self.try_put(value).expect("Unexpected error")
+    }
+    /// Same as [ValueOps::try_rmw] but panics on RawStore errors
+    #[inline(always)]
+    fn rmw<F: Sized>(&mut self, f: F) -> bool
+    where
+        F: FnMut(&mut V),
+    {
+        self.try_rmw(f).expect("Unexpected error")
+    }
+}
+
+/// Operations available for a ListIndex
+pub trait ListOps<K, V>: IndexOps
+where
+    K: Key,
+    V: Value,
+{
+    /// Appends a value to the list of a key
+    fn try_append(&mut self, key: K, value: V) -> Result<()>;
+    /// Iterates over the list of a key in append order
+    fn iter(&self, key: &K) -> list::Iter<'_, V>;
+    /// Removes the list of a key
+    fn try_clear(&mut self, key: &K) -> Result<()>;
+    /// Removes the list of a key, returning its values in append order
+    fn try_drain(&mut self, key: &K) -> Result<Vec<V>>;
+
+    /// Same as [ListOps::try_append] but panics on RawStore errors
+    #[inline(always)]
+    fn append(&mut self, key: K, value: V) {
+        self.try_append(key, value).expect("Unexpected error")
+    }
+    /// Same as [ListOps::try_clear] but panics on RawStore errors
+    #[inline(always)]
+    fn clear(&mut self, key: &K) {
+        self.try_clear(key).expect("Unexpected error")
+    }
+    /// Same as [ListOps::try_drain] but panics on RawStore errors
+    #[inline(always)]
+    fn drain(&mut self, key: &K) -> Vec<V> {
+        self.try_drain(key).expect("Unexpected error")
+    }
 }
@@ -0,0 +1,357 @@
+use crate::data::{key_prefix, Key, Value};
+use crate::error::*;
+use crate::index::hash::DefaultHashBuilder;
+use crate::index::recency::Recency;
+use crate::index::{IndexOps, WriteMode};
+use crate::raw_store::RawStore;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::rc::Rc;
+
+/// Number of members fetched from the RawStore per scan
+const SCAN_BATCH_SIZE: usize = 1024;
+
+/// Cached state of a single member
+struct Member {
+    /// Whether the value is a member of the key
+    present: bool,
+    /// Whether the state has changes that are not in the RawStore
+    modified: Cell<bool>,
+}
+
+impl Member {
+    #[inline]
+    fn new(present: bool, modified: bool) -> Self {
+        Member {
+            present,
+            modified: Cell::new(modified),
+        }
+    }
+}
+
+/// An Index that maps each key to a set of values
+///
+/// Every (key, value) pair is a record of its own in the RawStore, stored under
+/// the key followed by the value, so adding or removing a member never rewrites
+/// the other members of the key. Up to `capacity` member states, including known
+/// absences, are cached in memory.
+pub struct MultiMapIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value + Eq + Hash,
+{
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
+    /// Cached member states by key
+    members: HashMap<K, HashMap<V, Member, DefaultHashBuilder>, DefaultHashBuilder>,
+    /// Order in which the keys with cached members were last used
+    recency: Recency<K>,
+    /// Number of cached member states
+    cached: usize,
+    /// Max number of cached member states
+    capacity: usize,
+    /// Write Mode
+    mode: WriteMode,
+    /// The RawStore layer where things are persisted
+    raw_store: Rc<RefCell<RawStore>>,
+}
+
+impl<K, V> MultiMapIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value + Eq + Hash,
+{
+    /// Creates a MultiMapIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
+    #[inline]
+    pub fn new<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::default(), raw_store)
+    }
+
+    /// Creates a MultiMapIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::Cow, raw_store)
+    }
+
+    fn setup<I>(
+        namespace: I,
+        capacity: usize,
+        mode: WriteMode,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> MultiMapIndex<K, V>
+    where
+        I: Into<Vec<u8>>,
+    {
+        assert!(
+            capacity > 0,
+            "MultiMapIndex needs room for at least one member"
+        );
+        MultiMapIndex {
+            namespace: namespace.into(),
+            members: HashMap::default(),
+            recency: Recency::new(),
+            cached: 0,
+            capacity,
+            mode,
+            raw_store,
+        }
+    }
+
+    /// Returns the number of cached member states
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.cached
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cached == 0
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Adds a value to the members of a key
+    ///
+    /// Returns false if the value was already a member.
+    pub fn add(&mut self, key: K, value: V) -> Result<bool> {
+        if self.contains(&key, &value)? {
+            return Ok(false);
+        }
+        self.set(key, value, true)?;
+        Ok(true)
+    }
+
+    /// Removes a value from the members of a key
+    ///
+    /// Returns false if the value was not a member.
+    pub fn remove(&mut self, key: &K, value: &V) -> Result<bool> {
+        if !self.contains(key, value)? {
+            return Ok(false);
+        }
+        self.set(key.clone(), value.clone(), false)?;
+        Ok(true)
+    }
+
+    /// Returns whether a value is a member of a key
+    pub fn contains(&mut self, key: &K, value: &V) -> Result<bool> {
+        if let Some(member) = self.members.get(key).and_then(|members| members.get(value)) {
+            let present = member.present;
+            self.recency.touch(key);
+            return Ok(present);
+        }
+        let present = self
+            .raw_store
+            .borrow()
+            .get_bytes(&self.namespace, &member_key(key, value)?)?
+            .is_some();
+        self.cache(key.clone(), value.clone(), Member::new(present, false))?;
+        Ok(present)
+    }
+
+    /// Iterates over the members of a key in the order of their encoding
+    pub fn iter(&self, key: &K) -> Result<impl Iterator<Item = V>> {
+        let prefix = key_prefix(key)?;
+        let mut values = BTreeMap::new();
+        let mut from = prefix.clone();
+        let mut done = false;
+        while !done {
+            let records = self
+                .raw_store
+                .borrow()
+                .scan(&self.namespace, &from, SCAN_BATCH_SIZE)?;
+            done = records.len() < SCAN_BATCH_SIZE;
+            for (record_key, _) in records {
+                if !record_key.starts_with(&prefix) {
+                    done = true;
+                    break;
+                }
+                let raw_value = record_key[prefix.len()..].to_vec();
+                values.insert(raw_value.clone(), V::from_raw(&raw_value)?);
+                from = record_key;
+                from.push(0);
+            }
+        }
+
+        // Apply the changes that have not been persisted yet
+        if let Some(members) = self.members.get(key) {
+            for (value, member) in members.iter().filter(|(_, m)| m.modified.get()) {
+                let raw_value = value.into_raw()?;
+                if member.present {
+                    values.insert(raw_value, value.clone());
+                } else {
+                    values.remove(&raw_value);
+                }
+            }
+        }
+        Ok(values.into_values())
+    }
+
+    /// Updates the membership of a value and writes it through in COW mode
+    fn set(&mut self, key: K, value: V, present: bool) -> Result<()> {
+        if self.mode.is_cow() {
+            let raw_value = if present { Some(Vec::new()) } else { None };
+            let record = (member_key(&key, &value)?, raw_value);
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, vec![record])?;
+        }
+        let modified = self.mode.is_lazy();
+        self.cache(key, value, Member::new(present, modified))
+    }
+
+    /// Caches the state of a member, making room if needed
+    fn cache(&mut self, key: K, value: V, member: Member) -> Result<()> {
+        let cached = match self.members.get(&key) {
+            Some(members) => members.contains_key(&value),
+            None => false,
+        };
+        if !cached {
+            self.make_room()?;
+            self.cached += 1;
+        }
+        self.recency.touch(&key);
+        self.members.entry(key).or_default().insert(value, member);
+        Ok(())
+    }
+
+    /// Writes and drops the cached members of the least recently used keys until
+    /// there is room for one more
+    fn make_room(&mut self) -> Result<()> {
+        while self.cached >= self.capacity {
+            let key = match self.recency.oldest() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            if let Some(members) = self.members.get(&key) {
+                let mut records = Vec::new();
+                collect_records(&key, members, &mut records)?;
+                if !records.is_empty() {
+                    self.raw_store
+                        .borrow_mut()
+                        .write_bytes_batch(&self.namespace, records)?;
+                }
+            }
+            // Only drop the members once they are in the RawStore
+            if let Some(members) = self.members.remove(&key) {
+                self.cached -= members.len();
+            }
+            self.recency.remove(&key);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the RawStore key of a (key, value) pair
+#[inline]
+fn member_key<K: Key, V: Value>(key: &K, value: &V) -> Result<Vec<u8>> {
+    let mut record_key = key_prefix(key)?;
+    record_key.extend_from_slice(&value.into_raw()?);
+    Ok(record_key)
+}
+
+/// Collects the records of modified members, where removed members are deleted
+#[inline]
+fn collect_records<K, V>(
+    key: &K,
+    members: &HashMap<V, Member, DefaultHashBuilder>,
+    records: &mut Vec<(Vec<u8>, Option<Vec<u8>>)>,
+) -> Result<()>
+where
+    K: Key,
+    V: Value,
+{
+    for (value, member) in members.iter().filter(|(_, m)| m.modified.get()) {
+        let raw_value = if member.present {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        records.push((member_key(key, value)?, raw_value));
+    }
+    Ok(())
+}
+
+impl<K, V> IndexOps for MultiMapIndex<K, V>
+where
+    K: Key + Eq + Hash,
+    V: Value + Eq + Hash,
+{
+    fn persist(&self) -> Result<()> {
+        if self.mode.is_lazy() {
+            let mut records = Vec::new();
+            for (key, members) in self.members.iter() {
+                collect_records(key, members, &mut records)?;
+            }
+            if records.is_empty() {
+                return Ok(());
+            }
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, records)?;
+            for member in self.members.values().flat_map(|members| members.values()) {
+                member.modified.set(false);
+            }
+        }
+        // Else just ignore as COW copies on each modification
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn basic_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut sessions: MultiMapIndex<u64, u64> =
+            MultiMapIndex::new("sessions", 8, raw_store.clone());
+        // Spread the members over memory and disk
+        for user in 0..10 {
+            for session in 0..5 {
+                assert!(sessions.add(user, user * 100 + session).unwrap());
+            }
+        }
+        assert!(sessions.len() <= 8);
+        assert!(!sessions.add(3, 302).unwrap());
+        assert!(sessions.remove(&3, &302).unwrap());
+        assert!(!sessions.remove(&3, &302).unwrap());
+        assert!(!sessions.contains(&3, &302).unwrap());
+        assert!(sessions.contains(&3, &303).unwrap());
+        assert!(sessions.add(3, 0).unwrap());
+
+        assert_eq!(
+            sessions.iter(&3).unwrap().collect::<Vec<_>>(),
+            vec![0, 300, 301, 303, 304]
+        );
+        for user in 4..10 {
+            let expected: Vec<u64> = (0..5).map(|s| user * 100 + s).collect();
+            assert_eq!(sessions.iter(&user).unwrap().collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(sessions.iter(&42).unwrap().count(), 0);
+
+        // Members are picked up from the RawStore by a fresh index
+        sessions.persist().unwrap();
+        let mut other: MultiMapIndex<u64, u64> = MultiMapIndex::new("sessions", 8, raw_store);
+        assert_eq!(
+            other.iter(&3).unwrap().collect::<Vec<_>>(),
+            vec![0, 300, 301, 303, 304]
+        );
+        assert!(!other.contains(&3, &302).unwrap());
+    }
+}
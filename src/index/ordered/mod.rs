@@ -0,0 +1,495 @@
+use crate::data::{OrderedKey, Value};
+use crate::error::*;
+use crate::index::{IndexOps, OrderedOps, WriteMode};
+use crate::raw_store::scan::{successor, RawScan};
+use crate::raw_store::RawStore;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap};
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::rc::Rc;
+
+/// Number of records fetched from the RawStore per refill of a range scan
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// In-memory entry of an OrderedIndex
+struct Entry<V> {
+    value: V,
+    /// Whether the value has changes that are not in the RawStore
+    modified: Cell<bool>,
+}
+
+impl<V> Entry<V> {
+    #[inline]
+    fn new(value: V, modified: bool) -> Self {
+        Entry {
+            value,
+            modified: Cell::new(modified),
+        }
+    }
+}
+
+/// An Index that keeps its keys sorted
+///
+/// Entries are kept in an in-memory sorted structure. Once it is full, the lowest
+/// keys are spilled to the RawStore under their [OrderedKey] encoding, which keeps
+/// them in key order on disk as well. Range scans merge both sides.
+pub struct OrderedIndex<K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
+    /// In-memory sorted entries
+    entries: BTreeMap<K, Entry<V>>,
+    /// Max number of in-memory entries
+    capacity: usize,
+    /// Write Mode
+    mode: WriteMode,
+    /// The RawStore layer where things are persisted
+    raw_store: Rc<RefCell<RawStore>>,
+}
+
+impl<K, V> OrderedIndex<K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    /// Creates an OrderedIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
+    #[inline]
+    pub fn new<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::default(), raw_store)
+    }
+
+    /// Creates an OrderedIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::Cow, raw_store)
+    }
+
+    fn setup<I>(
+        namespace: I,
+        capacity: usize,
+        mode: WriteMode,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> OrderedIndex<K, V>
+    where
+        I: Into<Vec<u8>>,
+    {
+        assert!(
+            capacity > 0,
+            "OrderedIndex needs room for at least one entry"
+        );
+        OrderedIndex {
+            namespace: namespace.into(),
+            entries: BTreeMap::new(),
+            capacity,
+            mode,
+            raw_store,
+        }
+    }
+
+    /// Returns the number of in-memory entries
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Spills the lowest keys until there is room for one more entry
+    ///
+    /// An entry is only dropped from memory once its modifications are in the
+    /// RawStore.
+    fn make_room(&mut self) -> Result<()> {
+        while self.entries.len() >= self.capacity {
+            let (key, entry) = match self.entries.iter().next() {
+                Some(first) => first,
+                None => break,
+            };
+            if entry.modified.get() {
+                write(&self.raw_store, &self.namespace, key, &entry.value)?;
+            }
+            let key = key.clone();
+            self.entries.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Loads a key from the RawStore into memory unless it is already there
+    ///
+    /// Returns whether the key exists.
+    fn load(&mut self, key: &K) -> Result<bool> {
+        if self.entries.contains_key(key) {
+            return Ok(true);
+        }
+        let raw_opt = self
+            .raw_store
+            .borrow()
+            .get_bytes(&self.namespace, &key.to_ordered())?;
+        match raw_opt {
+            Some(raw) => {
+                let value = V::from_raw(&raw)?;
+                self.make_room()?;
+                self.entries.insert(key.clone(), Entry::new(value, false));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Writes a single entry to the RawStore
+#[inline]
+fn write<K, V>(raw_store: &RefCell<RawStore>, namespace: &[u8], key: &K, value: &V) -> Result<()>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    raw_store
+        .borrow_mut()
+        .put_bytes(namespace, &key.to_ordered(), &value.into_raw()?)
+}
+
+impl<K, V> IndexOps for OrderedIndex<K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    fn persist(&self) -> Result<()> {
+        // In COW mode, only entries whose write failed are still modified
+        let mut records = Vec::new();
+        for (key, entry) in self.entries.iter().filter(|(_, e)| e.modified.get()) {
+            records.push((key.to_ordered(), entry.value.into_raw()?));
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.raw_store
+            .borrow_mut()
+            .put_bytes_batch(&self.namespace, records)?;
+        for entry in self.entries.values() {
+            entry.modified.set(false);
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> OrderedOps<K, V> for OrderedIndex<K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    #[inline]
+    fn try_get(&mut self, key: &K) -> Result<Option<&V>> {
+        if self.load(key)? {
+            Ok(self.entries.get(key).map(|entry| &entry.value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[inline]
+    fn try_put(&mut self, key: K, value: V) -> Result<()> {
+        if self.mode.is_cow() {
+            write(&self.raw_store, &self.namespace, &key, &value)?;
+        }
+        let modified = self.mode.is_lazy();
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.value = value;
+                entry.modified.set(modified);
+            }
+            None => {
+                self.make_room()?;
+                self.entries.insert(key, Entry::new(value, modified));
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_rmw<F: Sized>(&mut self, key: &K, mut f: F) -> Result<bool>
+    where
+        F: FnMut(&mut V),
+    {
+        if !self.load(key)? {
+            // return false as the rmw operation did not modify the given key
+            return Ok(false);
+        }
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        f(&mut entry.value);
+        entry.modified.set(true);
+        if self.mode.is_cow() {
+            // The entry stays modified for the next persist if the write fails
+            write(&self.raw_store, &self.namespace, key, &entry.value)?;
+            entry.modified.set(false);
+        }
+        Ok(true)
+    }
+
+    #[inline]
+    fn range<R>(&mut self, range: R) -> Range<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let disk = DiskScan::new(self.raw_store.clone(), self.namespace.clone(), &start, &end);
+        Range {
+            memory: self.entries.range((start, end)).peekable(),
+            disk: disk.peekable(),
+        }
+    }
+}
+
+/// Iterator over a range of an OrderedIndex in ascending key order
+///
+/// Merges the in-memory entries with the entries that have been spilled to the
+/// RawStore. If a key exists on both sides, the in-memory version is returned.
+pub struct Range<'a, K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    memory: Peekable<btree_map::Range<'a, K, Entry<V>>>,
+    disk: Peekable<DiskScan<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().expect("Unexpected error")
+    }
+}
+
+impl<'a, K, V> Range<'a, K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    /// Returns the next entry, or the error that occurred while reading or decoding it
+    pub(crate) fn try_next(&mut self) -> Result<Option<(K, V)>> {
+        if let Some(Err(_)) = self.disk.peek() {
+            return self.disk.next().transpose();
+        }
+        let order = match (self.memory.peek(), self.disk.peek()) {
+            (Some((mem_key, _)), Some(Ok((disk_key, _)))) => mem_key.cmp(&disk_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return Ok(None),
+            (Some(_), Some(Err(_))) => unreachable!("errors are returned above"),
+        };
+        match order {
+            Ordering::Less => Ok(self.next_memory()),
+            Ordering::Equal => {
+                // The on-disk version is stale
+                self.disk.next().transpose()?;
+                Ok(self.next_memory())
+            }
+            Ordering::Greater => self.disk.next().transpose(),
+        }
+    }
+
+    #[inline]
+    fn next_memory(&mut self) -> Option<(K, V)> {
+        self.memory
+            .next()
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+    }
+}
+
+/// Scan over the spilled entries of an OrderedIndex
+struct DiskScan<K, V> {
+    raw: RawScan,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> DiskScan<K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    fn new(
+        raw_store: Rc<RefCell<RawStore>>,
+        namespace: Vec<u8>,
+        start: &Bound<K>,
+        end: &Bound<K>,
+    ) -> Self {
+        let from = match start {
+            Bound::Included(key) => key.to_ordered(),
+            Bound::Excluded(key) => successor(key.to_ordered()),
+            Bound::Unbounded => Vec::new(),
+        };
+        let end = match end {
+            Bound::Included(key) => Bound::Included(key.to_ordered()),
+            Bound::Excluded(key) => Bound::Excluded(key.to_ordered()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        DiskScan {
+            raw: RawScan::new(raw_store, namespace, from, end, SCAN_BATCH_SIZE),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Iterator for DiskScan<K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.raw.next()?;
+        Some(record.and_then(|(key, raw)| Ok((K::decode_ordered(&key)?, V::from_raw(&raw)?))))
+    }
+}
+
+/// Same as [Range] but yields errors instead of panicking on them
+pub struct TryRange<'a, K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    pub(crate) inner: Range<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for TryRange<'a, K, V>
+where
+    K: OrderedKey,
+    V: Value,
+{
+    type Item = Result<(K, V)>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.try_next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn basic_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: OrderedIndex<i64, u64> = OrderedIndex::new("index", 8, raw_store);
+        for i in -512..512 {
+            index.put(i, (i + 512) as u64);
+        }
+        assert_eq!(index.len(), 8);
+        for i in -512..512 {
+            assert_eq!(index.get(&i), Some(&((i + 512) as u64)));
+        }
+        assert!(index.rmw(&-512, |v| *v += 1));
+        assert_eq!(index.get(&-512), Some(&1));
+        assert!(!index.rmw(&1024, |v| *v += 1));
+        assert_eq!(index.get(&1024), None);
+        assert!(index.persist().is_ok());
+    }
+
+    #[test]
+    fn range_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: OrderedIndex<u64, u64> = OrderedIndex::new("index", 16, raw_store);
+        // Spread the keys over memory and disk, with stale versions on disk
+        for i in (0..1000).rev() {
+            index.put(i, i);
+        }
+        for i in (0..1000).step_by(10) {
+            index.put(i, i * 2);
+        }
+
+        let expected = |i: u64| if i % 10 == 0 { i * 2 } else { i };
+        let all: Vec<(u64, u64)> = index.range(..).collect();
+        assert_eq!(all.len(), 1000);
+        for (pos, (key, value)) in all.into_iter().enumerate() {
+            assert_eq!(key, pos as u64);
+            assert_eq!(value, expected(key));
+        }
+
+        let keys: Vec<u64> = index.range(250..=260).map(|(k, _)| k).collect();
+        assert_eq!(keys, (250..=260).collect::<Vec<_>>());
+        let keys: Vec<u64> = index
+            .range((Bound::Excluded(990), Bound::Unbounded))
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, (991..1000).collect::<Vec<_>>());
+        assert_eq!(index.range(2000..).count(), 0);
+    }
+
+    #[test]
+    fn try_range_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: OrderedIndex<u64, u64> = OrderedIndex::new("index", 4, raw_store.clone());
+        for i in 0..16 {
+            index.put(i, i);
+        }
+        let entries: Vec<(u64, u64)> = index.try_range(..).map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 16);
+
+        // A corrupt record on disk is handed to the caller
+        raw_store
+            .borrow_mut()
+            .put_bytes(b"index", &20u64.to_ordered(), &[0xff])
+            .unwrap();
+        let entries: Vec<Result<(u64, u64)>> = index.try_range(..).collect();
+        assert!(entries.iter().any(|e| e.is_err()));
+        assert!(index.try_range(..20).all(|e| e.is_ok()));
+    }
+
+    #[test]
+    fn cow_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: OrderedIndex<String, u64> = OrderedIndex::cow("index", 4, raw_store.clone());
+        index.put(String::from("b"), 2);
+        index.put(String::from("a"), 1);
+        assert!(index.rmw(&String::from("a"), |v| *v += 10));
+
+        // Writes went straight to the RawStore
+        let mut other: OrderedIndex<String, u64> = OrderedIndex::new("index", 4, raw_store);
+        let entries: Vec<(String, u64)> = other.range(..).collect();
+        assert_eq!(
+            entries,
+            vec![(String::from("a"), 11), (String::from("b"), 2)]
+        );
+    }
+}
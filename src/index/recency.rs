@@ -0,0 +1,60 @@
+use crate::index::hash::DefaultHashBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Order in which the keys held in memory by an index were last used
+///
+/// Indexes that evict whole keys use it to pick the least recently used one.
+pub(crate) struct Recency<K> {
+    /// Tick handed out to the next use
+    clock: u64,
+    /// Tick of the last use of each key
+    ticks: HashMap<K, u64, DefaultHashBuilder>,
+    /// Keys by the tick of their last use
+    order: BTreeMap<u64, K>,
+}
+
+impl<K> Recency<K>
+where
+    K: Clone + Eq + Hash,
+{
+    #[inline]
+    pub fn new() -> Self {
+        Recency {
+            clock: 0,
+            ticks: HashMap::default(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Marks a key as the most recently used one
+    pub fn touch(&mut self, key: &K) {
+        let tick = self.clock;
+        self.clock += 1;
+        match self.ticks.get_mut(key) {
+            Some(last) => {
+                let key = self.order.remove(last).expect("Unexpected error");
+                *last = tick;
+                self.order.insert(tick, key);
+            }
+            None => {
+                self.ticks.insert(key.clone(), tick);
+                self.order.insert(tick, key.clone());
+            }
+        }
+    }
+
+    /// Stops tracking a key that is no longer held in memory
+    #[inline]
+    pub fn remove(&mut self, key: &K) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Returns the least recently used key
+    #[inline]
+    pub fn oldest(&self) -> Option<&K> {
+        self.order.values().next()
+    }
+}
@@ -0,0 +1,127 @@
+use crate::data::Key;
+use crate::error::*;
+use crate::index::multimap::MultiMapIndex;
+use crate::index::IndexOps;
+use crate::raw_store::RawStore;
+use std::cell::RefCell;
+use std::hash::Hash;
+use std::rc::Rc;
+
+/// An Index holding a set of keys
+///
+/// Every member is a record of its own in the RawStore, so inserting or removing
+/// a member never rewrites the rest of the set. As [SetIndex::insert] reports
+/// whether a member is new, distinct counts can be kept alongside the set.
+pub struct SetIndex<K>
+where
+    K: Key + Eq + Hash,
+{
+    /// Members stored under a single unit key
+    members: MultiMapIndex<(), K>,
+}
+
+impl<K> SetIndex<K>
+where
+    K: Key + Eq + Hash,
+{
+    /// Creates a SetIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
+    #[inline]
+    pub fn new<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        SetIndex {
+            members: MultiMapIndex::new(namespace, capacity, raw_store),
+        }
+    }
+
+    /// Creates a SetIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        SetIndex {
+            members: MultiMapIndex::cow(namespace, capacity, raw_store),
+        }
+    }
+
+    /// Returns the number of cached member states
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.members.capacity()
+    }
+
+    /// Inserts a member, returning false if it was already in the set
+    #[inline]
+    pub fn insert(&mut self, member: K) -> Result<bool> {
+        self.members.add((), member)
+    }
+
+    #[inline]
+    pub fn contains(&mut self, member: &K) -> Result<bool> {
+        self.members.contains(&(), member)
+    }
+
+    /// Removes a member, returning false if it was not in the set
+    #[inline]
+    pub fn remove(&mut self, member: &K) -> Result<bool> {
+        self.members.remove(&(), member)
+    }
+
+    /// Iterates over the members in the order of their encoding
+    #[inline]
+    pub fn iter(&self) -> Result<impl Iterator<Item = K>> {
+        self.members.iter(&())
+    }
+}
+
+impl<K> IndexOps for SetIndex<K>
+where
+    K: Key + Eq + Hash,
+{
+    #[inline]
+    fn persist(&self) -> Result<()> {
+        self.members.persist()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn basic_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut users: SetIndex<String> = SetIndex::new("users", 2, raw_store.clone());
+        let mut distinct = 0;
+        for user in ["b", "a", "c", "a", "b", "d"].iter() {
+            if users.insert(user.to_string()).unwrap() {
+                distinct += 1;
+            }
+        }
+        assert_eq!(distinct, 4);
+        assert!(users.remove(&String::from("c")).unwrap());
+        assert!(!users.contains(&String::from("c")).unwrap());
+        users.persist().unwrap();
+
+        let other: SetIndex<String> = SetIndex::cow("users", 2, raw_store);
+        let members: Vec<String> = other.iter().unwrap().collect();
+        assert_eq!(members, vec!["a", "b", "d"]);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::data::{Key, OrderedKey};
+use crate::error::*;
+use crate::index::{IndexOps, WriteMode};
+use crate::raw_store::RawStore;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// A timer as (timestamp, encoded key)
+type Timer = (u64, Vec<u8>);
+
+/// An Index of timers per key, fired in timestamp order
+///
+/// Timers are kept in a priority queue whose earliest `capacity` timers are held
+/// in memory. Later timers are spilled to the RawStore under their timestamp
+/// followed by their key, which keeps them in firing order on disk, and are
+/// loaded back in batches once the in-memory timers have fired. Registering the
+/// same (key, timestamp) pair more than once yields a single timer.
+///
+/// The index works for any notion of time: event-time timers are popped with the
+/// watermark and processing-time timers with the wall clock.
+pub struct TimerIndex<K>
+where
+    K: Key,
+{
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
+    /// Earliest timers, mapped to whether they are in the RawStore
+    timers: BTreeMap<Timer, Cell<bool>>,
+    /// Latest in-memory timer if there are later timers in the RawStore
+    ///
+    /// Every timer up to the bound is in memory, every timer past it is only in
+    /// the RawStore.
+    bound: Option<Timer>,
+    /// Max number of in-memory timers
+    capacity: usize,
+    /// Write Mode
+    mode: WriteMode,
+    /// The RawStore layer where things are persisted
+    raw_store: Rc<RefCell<RawStore>>,
+    _marker: PhantomData<K>,
+}
+
+impl<K> TimerIndex<K>
+where
+    K: Key,
+{
+    /// Creates a TimerIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance.
+    #[inline]
+    pub fn new<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::default(), raw_store)
+    }
+
+    /// Creates a TimerIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(namespace, capacity, WriteMode::Cow, raw_store)
+    }
+
+    /// Creates a TimerIndex that picks up the timers persisted under `namespace`
+    pub fn open<I>(namespace: I, capacity: usize, raw_store: Rc<RefCell<RawStore>>) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        let mut index = Self::setup(namespace, capacity, WriteMode::default(), raw_store);
+        index.load(Vec::new())?;
+        Ok(index)
+    }
+
+    fn setup<I>(
+        namespace: I,
+        capacity: usize,
+        mode: WriteMode,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> TimerIndex<K>
+    where
+        I: Into<Vec<u8>>,
+    {
+        assert!(capacity > 0, "TimerIndex needs room for at least one timer");
+        TimerIndex {
+            namespace: namespace.into(),
+            timers: BTreeMap::new(),
+            bound: None,
+            capacity,
+            mode,
+            raw_store,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of in-memory timers
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns true if there are no timers, neither in memory nor in the RawStore
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty() && self.bound.is_none()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the timestamp of the earliest timer, if any
+    #[inline]
+    pub fn next_timestamp(&self) -> Option<u64> {
+        self.timers.keys().next().map(|(timestamp, _)| *timestamp)
+    }
+
+    /// Registers a timer for `key` that fires at `timestamp`
+    pub fn register(&mut self, key: &K, timestamp: u64) -> Result<()> {
+        let timer = (timestamp, key.into_raw()?);
+        if self.is_spilled(&timer) {
+            return self.write(&timer);
+        }
+        if !self.timers.contains_key(&timer) {
+            let stored = self.mode.is_cow();
+            if stored {
+                self.write(&timer)?;
+            }
+            self.timers.insert(timer, Cell::new(stored));
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the timer for `key` at `timestamp` if it exists
+    pub fn delete(&mut self, key: &K, timestamp: u64) -> Result<()> {
+        let timer = (timestamp, key.into_raw()?);
+        let stored = match self.timers.remove(&timer) {
+            Some(stored) => stored.get(),
+            None => self.is_spilled(&timer),
+        };
+        if stored {
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, vec![(timer_key(&timer), None)])?;
+        }
+        Ok(())
+    }
+
+    /// Removes and returns the timers that fire at or before `time` in timestamp order
+    pub fn pop_expired(&mut self, time: u64) -> Result<Vec<(K, u64)>> {
+        let mut expired = Vec::new();
+        let mut deletes = Vec::new();
+        loop {
+            let timer = match self.timers.keys().next() {
+                Some(timer) if timer.0 <= time => timer.clone(),
+                Some(_) => break,
+                None => match self.bound.take() {
+                    Some(bound) => {
+                        let mut from = timer_key(&bound);
+                        from.push(0);
+                        self.load(from)?;
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            if let Some(stored) = self.timers.remove(&timer) {
+                if stored.get() {
+                    deletes.push((timer_key(&timer), None));
+                }
+            }
+            expired.push((K::from_raw(&timer.1)?, timer.0));
+        }
+        if !deletes.is_empty() {
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, deletes)?;
+        }
+        Ok(expired)
+    }
+
+    /// Whether a timer would be past the in-memory timers
+    #[inline]
+    fn is_spilled(&self, timer: &Timer) -> bool {
+        match &self.bound {
+            Some(bound) => timer > bound,
+            None => false,
+        }
+    }
+
+    /// Moves the latest timers to the RawStore until the in-memory timers fit
+    fn spill(&mut self) -> Result<()> {
+        let mut records = Vec::new();
+        while self.timers.len() > self.capacity {
+            let timer = match self.timers.keys().next_back() {
+                Some(timer) => timer.clone(),
+                None => break,
+            };
+            if let Some(stored) = self.timers.remove(&timer) {
+                if !stored.get() {
+                    records.push((timer_key(&timer), Vec::new()));
+                }
+            }
+            self.bound = self.timers.keys().next_back().cloned();
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.raw_store
+            .borrow_mut()
+            .put_bytes_batch(&self.namespace, records)
+    }
+
+    /// Loads up to `capacity` timers from the RawStore, starting at the encoded timer `from`
+    fn load(&mut self, from: Vec<u8>) -> Result<()> {
+        let records = self
+            .raw_store
+            .borrow()
+            .scan(&self.namespace, &from, self.capacity)?;
+        let full = records.len() == self.capacity;
+        for (record_key, _) in records {
+            self.timers
+                .insert(decode_timer_key(&record_key)?, Cell::new(true));
+        }
+        self.bound = if full {
+            self.timers.keys().next_back().cloned()
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// Writes a single timer to the RawStore
+    #[inline]
+    fn write(&self, timer: &Timer) -> Result<()> {
+        self.raw_store
+            .borrow_mut()
+            .put_bytes(&self.namespace, &timer_key(timer), &[])
+    }
+}
+
+/// Returns the RawStore key of a timer
+#[inline]
+fn timer_key(timer: &Timer) -> Vec<u8> {
+    let mut key = Vec::with_capacity(8 + timer.1.len());
+    timer.0.encode_ordered(&mut key);
+    key.extend_from_slice(&timer.1);
+    key
+}
+
+#[inline]
+fn decode_timer_key(key: &[u8]) -> Result<Timer> {
+    if key.len() < 8 {
+        return Err(BrittMarieError::Serde(format!(
+            "expected at least 8 timer key bytes, found {}",
+            key.len()
+        )));
+    }
+    Ok((u64::decode_ordered(&key[..8])?, key[8..].to_vec()))
+}
+
+impl<K> IndexOps for TimerIndex<K>
+where
+    K: Key,
+{
+    fn persist(&self) -> Result<()> {
+        if self.mode.is_lazy() {
+            let records: Vec<_> = self
+                .timers
+                .iter()
+                .filter(|(_, stored)| !stored.get())
+                .map(|(timer, _)| (timer_key(timer), Vec::new()))
+                .collect();
+            if records.is_empty() {
+                return Ok(());
+            }
+            self.raw_store
+                .borrow_mut()
+                .put_bytes_batch(&self.namespace, records)?;
+            for stored in self.timers.values() {
+                stored.set(true);
+            }
+        }
+        // Else just ignore as COW copies on each registration
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn basic_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut timers: TimerIndex<u64> = TimerIndex::new("timers", 8, raw_store.clone());
+        // Spread the timers over memory and disk, in reverse order
+        for ts in (0..100).rev() {
+            timers.register(&(ts % 7), ts).unwrap();
+            // Duplicates are ignored
+            timers.register(&(ts % 7), ts).unwrap();
+        }
+        assert_eq!(timers.len(), 8);
+        timers.delete(&3, 10).unwrap();
+        timers.delete(&1, 99).unwrap();
+        timers.delete(&1, 98).unwrap();
+
+        let expired = timers.pop_expired(20).unwrap();
+        assert_eq!(expired.len(), 20);
+        assert_eq!(expired[0], (0, 0));
+        assert!(expired.windows(2).all(|w| w[0].1 < w[1].1));
+        assert!(!expired.contains(&(3, 10)));
+        assert_eq!(timers.next_timestamp(), Some(21));
+        assert!(timers.persist().is_ok());
+
+        // Timers survive a restart
+        let mut reopened: TimerIndex<u64> = TimerIndex::open("timers", 8, raw_store).unwrap();
+        let expired = reopened.pop_expired(u64::MAX).unwrap();
+        assert_eq!(expired.len(), 78);
+        assert_eq!(expired[0], (0, 21));
+        assert_eq!(expired.last(), Some(&(0, 98)));
+        assert!(reopened.is_empty());
+    }
+
+    #[test]
+    fn cow_test() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut timers: TimerIndex<String> = TimerIndex::cow("timers", 4, raw_store.clone());
+        timers.register(&String::from("b"), 5).unwrap();
+        timers.register(&String::from("a"), 5).unwrap();
+        timers.register(&String::from("a"), 1).unwrap();
+
+        // Registrations went straight to the RawStore
+        let mut other: TimerIndex<String> = TimerIndex::open("timers", 4, raw_store).unwrap();
+        assert_eq!(
+            other.pop_expired(5).unwrap(),
+            vec![
+                (String::from("a"), 1),
+                (String::from("a"), 5),
+                (String::from("b"), 5)
+            ]
+        );
+    }
+}
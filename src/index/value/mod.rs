@@ -1,10 +1,13 @@
-use crate::data::Value;
+use crate::data::{Key, OrderedKey, Value};
 use crate::error::*;
 use crate::index::{IndexOps, ValueOps, WriteMode};
 use crate::raw_store::RawStore;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// Number of versions fetched per RawStore scan
+const VERSION_SCAN_BATCH: usize = 1024;
+
 /// An Index suitable for single value operations
 ///
 /// Examples include rolling counters, watermarks, and epochs.
@@ -14,12 +17,19 @@ where
 {
     /// Raw key for this Value
     ///
-    /// Should be unique within the RawStore instance
+    /// Should be unique within the RawStore instance as it is also
+    /// the RawStore namespace of the index.
     key: Vec<u8>,
     /// The data itself
     data: Option<V>,
     /// Write Mode
     mode: WriteMode,
+    /// Latest version written in COW mode, 0 if there is none
+    version: u64,
+    /// Oldest version that may still be retained
+    oldest: u64,
+    /// Number of versions to retain, all of them if unset
+    retention: Option<usize>,
     /// Reference to the RawStore
     raw_store: Rc<RefCell<RawStore>>,
 }
@@ -28,7 +38,6 @@ impl<V> ValueIndex<V>
 where
     V: Value,
 {
-
     /// Creates a ValueIndex using the default lazy [WriteMode]
     pub fn new<I>(key: I, raw_store: Rc<RefCell<RawStore>>) -> Self
     where
@@ -45,6 +54,43 @@ where
         Self::setup(key, WriteMode::Cow, raw_store)
     }
 
+    /// Creates a ValueIndex holding the value persisted under `key`
+    ///
+    /// Starts out with `V::default()` if nothing has been persisted yet.
+    pub fn open<I>(key: I, raw_store: Rc<RefCell<RawStore>>) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::recover(key, WriteMode::default(), false, raw_store)
+    }
+
+    /// Same as [ValueIndex::open] but with Copy-On-Write enabled
+    pub fn open_cow<I>(key: I, raw_store: Rc<RefCell<RawStore>>) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::recover(key, WriteMode::Cow, false, raw_store)
+    }
+
+    /// Creates a ValueIndex holding the value persisted under `key`
+    ///
+    /// Fails with [BrittMarieError::NotFound] if nothing has been persisted yet,
+    /// e.g., when restoring from a checkpoint that is expected to hold the value.
+    pub fn restore<I>(key: I, raw_store: Rc<RefCell<RawStore>>) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::recover(key, WriteMode::default(), true, raw_store)
+    }
+
+    /// Same as [ValueIndex::restore] but with Copy-On-Write enabled
+    pub fn restore_cow<I>(key: I, raw_store: Rc<RefCell<RawStore>>) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::recover(key, WriteMode::Cow, true, raw_store)
+    }
+
     fn setup<I>(key: I, mode: WriteMode, raw_store: Rc<RefCell<RawStore>>) -> ValueIndex<V>
     where
         I: Into<Vec<u8>>,
@@ -53,9 +99,139 @@ where
             key: key.into(),
             data: Some(V::default()),
             mode,
+            version: 0,
+            oldest: 1,
+            retention: None,
             raw_store,
         }
     }
+
+    /// Keeps only the latest `versions` versions of the value in COW mode
+    ///
+    /// Older versions are removed from the RawStore on the next update.
+    pub fn with_retention(mut self, versions: usize) -> Self {
+        assert!(versions > 0, "retention must keep at least one version");
+        self.retention = Some(versions);
+        self
+    }
+
+    /// Returns the latest version of the value, 0 if no version has been written
+    ///
+    /// Only COW updates create versions, starting at 1.
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the retained versions of the value in ascending version order
+    pub fn history(&self) -> Result<Vec<(u64, V)>> {
+        let mut history = Vec::new();
+        self.scan_versions(|version, raw_value| {
+            history.push((version, V::from_raw(&raw_value)?));
+            Ok(())
+        })?;
+        Ok(history)
+    }
+
+    /// Returns the value as of `version`, if that version is still retained
+    pub fn value_at(&self, version: u64) -> Result<Option<V>> {
+        let raw_value = self
+            .raw_store
+            .borrow()
+            .get_bytes(&self.key, &version.to_ordered())?;
+        match raw_value {
+            Some(raw_value) => V::from_raw(&raw_value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Visits the versions stored under the namespace of the index in order
+    fn scan_versions<F>(&self, mut visit: F) -> Result<()>
+    where
+        F: FnMut(u64, Vec<u8>) -> Result<()>,
+    {
+        let mut from = 0u64;
+        loop {
+            let records =
+                self.raw_store
+                    .borrow()
+                    .scan(&self.key, &from.to_ordered(), VERSION_SCAN_BATCH)?;
+            let done = records.len() < VERSION_SCAN_BATCH;
+            for (raw_key, raw_value) in records {
+                let version = u64::decode_ordered(&raw_key)?;
+                visit(version, raw_value)?;
+                from = version + 1;
+            }
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Writes the value as a new version in a single batch together with the
+    /// current value, removing versions that fall outside the retention bound
+    fn append(&mut self) -> Result<()> {
+        let data = match &self.data {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let raw_value = data.into_raw()?;
+        let version = self.version + 1;
+        let oldest = match self.retention {
+            Some(retention) => {
+                u64::max(self.oldest, (version + 1).saturating_sub(retention as u64))
+            }
+            None => self.oldest,
+        };
+        let mut records = vec![
+            (Key::into_raw(&())?, Some(raw_value.clone())),
+            (version.to_ordered(), Some(raw_value)),
+        ];
+        records.extend((self.oldest..oldest).map(|expired| (expired.to_ordered(), None)));
+        self.raw_store
+            .borrow_mut()
+            .write_bytes_batch(&self.key, records)?;
+        self.version = version;
+        self.oldest = oldest;
+        Ok(())
+    }
+
+    fn recover<I>(
+        key: I,
+        mode: WriteMode,
+        required: bool,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Result<ValueIndex<V>>
+    where
+        I: Into<Vec<u8>>,
+    {
+        let mut index = Self::setup(key, mode, raw_store);
+        let stored = index.raw_store.borrow().get(&index.key, &())?;
+        match stored {
+            Some(value) => index.data = Some(value),
+            None if required => {
+                return Err(BrittMarieError::NotFound(
+                    String::from_utf8_lossy(&index.key).into_owned(),
+                ))
+            }
+            None => (),
+        }
+        if index.mode.is_cow() {
+            let mut versions = None;
+            index.scan_versions(|version, _| {
+                versions = Some(match versions {
+                    Some((oldest, _)) => (oldest, version),
+                    None => (version, version),
+                });
+                Ok(())
+            })?;
+            if let Some((oldest, latest)) = versions {
+                index.oldest = oldest;
+                index.version = latest;
+            }
+        }
+        Ok(index)
+    }
 }
 
 impl<V> IndexOps for ValueIndex<V>
@@ -64,9 +240,7 @@ where
 {
     fn persist(&self) -> Result<()> {
         if let Some(data) = &self.data {
-            self.raw_store
-                .borrow_mut()
-                .put(&self.key, data)?;
+            self.raw_store.borrow_mut().put(&self.key, &(), data)?;
         }
 
         Ok(())
@@ -82,24 +256,27 @@ where
         self.data.as_ref()
     }
     #[inline(always)]
-    fn put(&mut self, value: V) {
+    fn try_put(&mut self, value: V) -> Result<()> {
         self.data = Some(value);
         if self.mode.is_cow() {
-            let _ = self.persist();
+            self.append()?;
         }
+        Ok(())
     }
     #[inline(always)]
-    fn rmw<F: Sized>(&mut self, mut f: F) -> bool
+    fn try_rmw<F: Sized>(&mut self, mut f: F) -> Result<bool>
     where
         F: FnMut(&mut V),
     {
-        if let Some(ref mut v) = self.data.as_mut() {
-            f(v);
-            if self.mode.is_cow() {
-                let _ = self.persist();
-            }
+        match self.data.as_mut() {
+            Some(v) => f(v),
+            // return false as there is no value to modify
+            None => return Ok(false),
+        }
+        if self.mode.is_cow() {
+            self.append()?;
         }
-        return true;
+        Ok(true)
     }
 }
 
@@ -111,6 +288,8 @@ mod tests {
     fn basic_test() {
         let raw_store = Rc::new(RefCell::new(RawStore::new("/tmp/value")));
         let mut value_index: ValueIndex<u64> = ValueIndex::new("_myvaluekey", raw_store);
+        value_index.data = None;
+        assert_eq!(value_index.rmw(|v| *v += 10), false);
         value_index.put(10);
         assert_eq!(value_index.get(), Some(&10));
         assert_eq!(
@@ -121,4 +300,62 @@ mod tests {
         );
         assert_eq!(value_index.get(), Some(&20));
     }
+
+    #[test]
+    fn recover_test() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+
+        assert!(matches!(
+            ValueIndex::<u64>::restore("_watermark", raw_store.clone()),
+            Err(BrittMarieError::NotFound(_))
+        ));
+        let mut watermark: ValueIndex<u64> =
+            ValueIndex::open("_watermark", raw_store.clone()).unwrap();
+        assert_eq!(watermark.get(), Some(&0));
+        watermark.put(100);
+        assert!(watermark.persist().is_ok());
+
+        let watermark: ValueIndex<u64> =
+            ValueIndex::restore("_watermark", raw_store.clone()).unwrap();
+        assert_eq!(watermark.get(), Some(&100));
+        let mut epoch: ValueIndex<u64> = ValueIndex::open_cow("_epoch", raw_store.clone()).unwrap();
+        epoch.put(3);
+        let epoch: ValueIndex<u64> = ValueIndex::restore_cow("_epoch", raw_store).unwrap();
+        assert_eq!(epoch.get(), Some(&3));
+    }
+
+    #[test]
+    fn history_test() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+
+        let mut epoch: ValueIndex<u64> =
+            ValueIndex::cow("_epoch", raw_store.clone()).with_retention(3);
+        for value in 1..=5 {
+            epoch.put(value * 10);
+        }
+        assert_eq!(epoch.version(), 5);
+        assert_eq!(epoch.history().unwrap(), vec![(3, 30), (4, 40), (5, 50)]);
+        assert_eq!(epoch.value_at(2).unwrap(), None);
+        assert_eq!(epoch.value_at(4).unwrap(), Some(40));
+
+        // Versioning continues where it left off
+        let mut epoch: ValueIndex<u64> = ValueIndex::restore_cow("_epoch", raw_store.clone())
+            .unwrap()
+            .with_retention(2);
+        assert_eq!(epoch.version(), 5);
+        assert_eq!(epoch.get(), Some(&50));
+        epoch.rmw(|v| *v += 1);
+        assert_eq!(epoch.history().unwrap(), vec![(5, 50), (6, 51)]);
+
+        // Lazy writes are not versioned
+        let mut watermark: ValueIndex<u64> = ValueIndex::new("_watermark", raw_store);
+        watermark.put(7);
+        watermark.persist().unwrap();
+        assert_eq!(watermark.version(), 0);
+        assert!(watermark.history().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,718 @@
+use crate::data::{Key, OrderedKey, Value};
+use crate::error::*;
+use crate::index::hash::DefaultHashBuilder;
+use crate::index::{IndexOps, WindowAssigner, WriteMode};
+use crate::raw_store::RawStore;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Number of records fetched from the RawStore per scan when opening an index
+const SCAN_BATCH_SIZE: usize = 1024;
+
+/// Key of the watermark within the namespace of an index
+///
+/// Window keys are at least 16 bytes long, so it never clashes with one.
+const WATERMARK_KEY: &[u8] = &[];
+
+/// An event-time window covering the timestamps `start..end`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Window {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Window {
+    #[inline]
+    pub fn new(start: u64, end: u64) -> Self {
+        Window { start, end }
+    }
+
+    /// Whether the window is closed once the watermark has reached `watermark`
+    #[inline]
+    pub fn is_closed(&self, watermark: u64) -> bool {
+        self.end <= watermark
+    }
+}
+
+// Windows are ordered by their end first so that they close in order
+impl Ord for Window {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.end, self.start).cmp(&(other.end, other.start))
+    }
+}
+
+impl PartialOrd for Window {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// In-memory accumulator of a (key, window) pair
+struct Entry<A> {
+    value: A,
+    /// Whether the value has changes that are not in the RawStore
+    modified: Cell<bool>,
+    /// Whether a version of the value is in the RawStore
+    stored: Cell<bool>,
+}
+
+impl<A> Entry<A> {
+    #[inline]
+    fn new(value: A, stored: bool) -> Self {
+        Entry {
+            value,
+            modified: Cell::new(false),
+            stored: Cell::new(stored),
+        }
+    }
+}
+
+/// An Index for event-time windowed aggregation
+///
+/// Events are assigned to windows by a [WindowAssigner] and folded into one
+/// accumulator per (key, window) pair. Accumulators are stored under the end of
+/// their window in the RawStore, and up to `capacity` of them are kept in memory,
+/// spilling the ones that close last. Advancing the watermark returns the
+/// windows that have closed and removes their state.
+///
+/// The set of open (key, window) pairs is tracked in memory so that windows can
+/// be fired and sessions merged without RawStore scans. [WindowIndex::open]
+/// rebuilds it from the RawStore after a restart.
+pub struct WindowIndex<K, A>
+where
+    K: Key + Eq + Hash,
+    A: Value,
+{
+    /// RawStore namespace of the index
+    namespace: Vec<u8>,
+    /// Assigns events to windows
+    assigner: WindowAssigner,
+    /// Merges the accumulators of two session windows
+    merge: Option<fn(&mut A, A)>,
+    /// In-memory accumulators by window and encoded key
+    entries: BTreeMap<(Window, Vec<u8>), Entry<A>>,
+    /// All open (window, encoded key) pairs, in memory or in the RawStore
+    open: BTreeSet<(Window, Vec<u8>)>,
+    /// Open session windows by encoded key
+    sessions: HashMap<Vec<u8>, Vec<Window>, DefaultHashBuilder>,
+    /// Max number of in-memory accumulators
+    capacity: usize,
+    /// Latest watermark
+    watermark: u64,
+    /// Write Mode
+    mode: WriteMode,
+    /// The RawStore layer where things are persisted
+    raw_store: Rc<RefCell<RawStore>>,
+    _marker: PhantomData<K>,
+}
+
+impl<K, A> WindowIndex<K, A>
+where
+    K: Key + Eq + Hash,
+    A: Value,
+{
+    /// Creates a WindowIndex using the default lazy WriteMode
+    ///
+    /// The namespace should be unique within the RawStore instance. Session windows
+    /// are created through [WindowIndex::session] instead.
+    #[inline]
+    pub fn new<I>(
+        namespace: I,
+        assigner: WindowAssigner,
+        capacity: usize,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(
+            namespace,
+            assigner,
+            None,
+            capacity,
+            WriteMode::default(),
+            raw_store,
+        )
+    }
+
+    /// Creates a WindowIndex with Copy-On-Write enabled
+    #[inline]
+    pub fn cow<I>(
+        namespace: I,
+        assigner: WindowAssigner,
+        capacity: usize,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(
+            namespace,
+            assigner,
+            None,
+            capacity,
+            WriteMode::Cow,
+            raw_store,
+        )
+    }
+
+    /// Creates a WindowIndex of session windows using the default lazy WriteMode
+    ///
+    /// `merge` folds the accumulator of a session into the one of an overlapping
+    /// session when the two are merged.
+    #[inline]
+    pub fn session<I>(
+        namespace: I,
+        gap: u64,
+        merge: fn(&mut A, A),
+        capacity: usize,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(
+            namespace,
+            WindowAssigner::Session { gap },
+            Some(merge),
+            capacity,
+            WriteMode::default(),
+            raw_store,
+        )
+    }
+
+    /// Same as [WindowIndex::session] but with Copy-On-Write enabled
+    #[inline]
+    pub fn session_cow<I>(
+        namespace: I,
+        gap: u64,
+        merge: fn(&mut A, A),
+        capacity: usize,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(
+            namespace,
+            WindowAssigner::Session { gap },
+            Some(merge),
+            capacity,
+            WriteMode::Cow,
+            raw_store,
+        )
+    }
+
+    /// Creates a WindowIndex that picks up the open windows and the watermark
+    /// persisted under `namespace`
+    pub fn open<I>(
+        namespace: I,
+        assigner: WindowAssigner,
+        capacity: usize,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(
+            namespace,
+            assigner,
+            None,
+            capacity,
+            WriteMode::default(),
+            raw_store,
+        )
+        .recover()
+    }
+
+    /// Same as [WindowIndex::open] but for session windows, see [WindowIndex::session]
+    pub fn open_session<I>(
+        namespace: I,
+        gap: u64,
+        merge: fn(&mut A, A),
+        capacity: usize,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> Result<Self>
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self::setup(
+            namespace,
+            WindowAssigner::Session { gap },
+            Some(merge),
+            capacity,
+            WriteMode::default(),
+            raw_store,
+        )
+        .recover()
+    }
+
+    fn setup<I>(
+        namespace: I,
+        assigner: WindowAssigner,
+        merge: Option<fn(&mut A, A)>,
+        capacity: usize,
+        mode: WriteMode,
+        raw_store: Rc<RefCell<RawStore>>,
+    ) -> WindowIndex<K, A>
+    where
+        I: Into<Vec<u8>>,
+    {
+        assert!(
+            capacity > 0,
+            "WindowIndex needs room for at least one window"
+        );
+        match assigner {
+            WindowAssigner::Tumbling { size } => assert!(size > 0, "window size must be positive"),
+            WindowAssigner::Sliding { size, slide } => {
+                assert!(
+                    size > 0 && slide > 0,
+                    "window size and slide must be positive"
+                )
+            }
+            WindowAssigner::Session { gap } => {
+                assert!(gap > 0, "session gap must be positive");
+                assert!(
+                    merge.is_some(),
+                    "session windows need a merge function, see WindowIndex::session"
+                );
+            }
+        }
+        WindowIndex {
+            namespace: namespace.into(),
+            assigner,
+            merge,
+            entries: BTreeMap::new(),
+            open: BTreeSet::new(),
+            sessions: HashMap::default(),
+            capacity,
+            watermark: 0,
+            mode,
+            raw_store,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Rebuilds the set of open windows and the watermark from the RawStore
+    fn recover(mut self) -> Result<Self> {
+        let mut from = Vec::new();
+        loop {
+            let records = self
+                .raw_store
+                .borrow()
+                .scan(&self.namespace, &from, SCAN_BATCH_SIZE)?;
+            let done = records.len() < SCAN_BATCH_SIZE;
+            for (record_key, raw_value) in records {
+                if record_key == WATERMARK_KEY {
+                    self.watermark = <u64 as Value>::from_raw(&raw_value)?;
+                } else {
+                    let (window, raw_key) = decode_window_key(&record_key)?;
+                    self.register(window, raw_key);
+                }
+                from = record_key;
+                from.push(0);
+            }
+            if done {
+                return Ok(self);
+            }
+        }
+    }
+
+    /// Returns the number of open windows over all keys
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    /// Folds an event with timestamp `timestamp` into the windows it belongs to
+    ///
+    /// Returns false if the event belongs to no open window, e.g., because it is
+    /// late and all its windows have closed.
+    pub fn insert<F>(&mut self, key: K, timestamp: u64, mut f: F) -> Result<bool>
+    where
+        F: FnMut(&mut A),
+    {
+        let raw_key = key.into_raw()?;
+        let windows = match self.assigner {
+            WindowAssigner::Session { gap } => {
+                let window = Window::new(timestamp, timestamp.saturating_add(gap));
+                if window.is_closed(self.watermark) {
+                    Vec::new()
+                } else {
+                    vec![self.merge_sessions(&raw_key, window)?]
+                }
+            }
+            _ => {
+                let watermark = self.watermark;
+                let mut windows = self.assigner.assign(timestamp);
+                windows.retain(|window| !window.is_closed(watermark));
+                windows
+            }
+        };
+
+        let cow = self.mode.is_cow();
+        for window in windows.iter() {
+            self.load(*window, &raw_key)?;
+            let entry = self
+                .entries
+                .get_mut(&(*window, raw_key.clone()))
+                .expect("Unexpected error");
+            f(&mut entry.value);
+            entry.modified.set(true);
+            if cow {
+                let entry = &self.entries[&(*window, raw_key.clone())];
+                self.write(*window, &raw_key, entry)?;
+            }
+        }
+        Ok(!windows.is_empty())
+    }
+
+    /// Advances the watermark and returns the windows that closed in window order
+    ///
+    /// The state of the closed windows is removed from memory and the RawStore,
+    /// together with which the new watermark is written.
+    pub fn advance_watermark(&mut self, watermark: u64) -> Result<Vec<(K, Window, A)>> {
+        let mut records = Vec::new();
+        if watermark > self.watermark {
+            self.watermark = watermark;
+            records.push((WATERMARK_KEY.to_vec(), Some(Value::into_raw(&watermark)?)));
+        }
+        let mut results = Vec::new();
+        while let Some((window, raw_key)) = self.open.iter().next().cloned() {
+            if !window.is_closed(self.watermark) {
+                break;
+            }
+            if let Some((value, stored)) = self.take(window, &raw_key)? {
+                if stored {
+                    records.push((window_key(window, &raw_key), None));
+                }
+                results.push((K::from_raw(&raw_key)?, window, value));
+            }
+        }
+        if !records.is_empty() {
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, records)?;
+        }
+        Ok(results)
+    }
+
+    /// Merges a new session window with the open sessions of a key that it overlaps
+    fn merge_sessions(&mut self, raw_key: &[u8], window: Window) -> Result<Window> {
+        let overlapping: Vec<Window> = match self.sessions.get(raw_key) {
+            Some(sessions) => sessions
+                .iter()
+                .filter(|s| s.start < window.end && window.start < s.end)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        match overlapping.as_slice() {
+            [] => return Ok(window),
+            [session] if session.start <= window.start && window.end <= session.end => {
+                return Ok(*session)
+            }
+            _ => (),
+        }
+
+        let merge = self.merge.expect("checked in WindowIndex::setup");
+        let mut merged = window;
+        let mut value: Option<A> = None;
+        let mut deletes = Vec::new();
+        for session in overlapping {
+            merged.start = u64::min(merged.start, session.start);
+            merged.end = u64::max(merged.end, session.end);
+            if let Some((session_value, stored)) = self.take(session, raw_key)? {
+                if stored {
+                    deletes.push((window_key(session, raw_key), None));
+                }
+                match value.as_mut() {
+                    Some(value) => merge(value, session_value),
+                    None => value = Some(session_value),
+                }
+            }
+        }
+        if !deletes.is_empty() {
+            self.raw_store
+                .borrow_mut()
+                .write_bytes_batch(&self.namespace, deletes)?;
+        }
+
+        self.make_room()?;
+        let entry = Entry::new(value.unwrap_or_default(), false);
+        entry.modified.set(true);
+        self.entries.insert((merged, raw_key.to_vec()), entry);
+        self.register(merged, raw_key.to_vec());
+        Ok(merged)
+    }
+
+    /// Brings the accumulator of a (key, window) pair into memory
+    ///
+    /// Windows that are not open yet start out with `A::default()`.
+    fn load(&mut self, window: Window, raw_key: &[u8]) -> Result<()> {
+        let id = (window, raw_key.to_vec());
+        if self.entries.contains_key(&id) {
+            return Ok(());
+        }
+        let entry = if self.open.contains(&id) {
+            let raw_value = self
+                .raw_store
+                .borrow()
+                .get_bytes(&self.namespace, &window_key(window, raw_key))?;
+            match raw_value {
+                Some(raw_value) => Entry::new(A::from_raw(&raw_value)?, true),
+                None => Entry::new(A::default(), false),
+            }
+        } else {
+            self.register(window, raw_key.to_vec());
+            Entry::new(A::default(), false)
+        };
+        self.make_room()?;
+        self.entries.insert(id, entry);
+        Ok(())
+    }
+
+    /// Removes the accumulator of a (key, window) pair from memory and the set of
+    /// open windows, returning it along with whether it is in the RawStore
+    fn take(&mut self, window: Window, raw_key: &[u8]) -> Result<Option<(A, bool)>> {
+        let id = (window, raw_key.to_vec());
+        self.unregister(&id);
+        if let Some(entry) = self.entries.remove(&id) {
+            return Ok(Some((entry.value, entry.stored.get())));
+        }
+        let raw_value = self
+            .raw_store
+            .borrow()
+            .get_bytes(&self.namespace, &window_key(window, raw_key))?;
+        match raw_value {
+            Some(raw_value) => Ok(Some((A::from_raw(&raw_value)?, true))),
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    fn register(&mut self, window: Window, raw_key: Vec<u8>) {
+        if self.assigner.is_session() {
+            self.sessions
+                .entry(raw_key.clone())
+                .or_default()
+                .push(window);
+        }
+        self.open.insert((window, raw_key));
+    }
+
+    #[inline]
+    fn unregister(&mut self, id: &(Window, Vec<u8>)) {
+        self.open.remove(id);
+        if let Some(sessions) = self.sessions.get_mut(&id.1) {
+            sessions.retain(|session| *session != id.0);
+            if sessions.is_empty() {
+                self.sessions.remove(&id.1);
+            }
+        }
+    }
+
+    /// Spills the accumulators that close last until there is room for one more
+    fn make_room(&mut self) -> Result<()> {
+        while self.entries.len() >= self.capacity {
+            let id = match self.entries.keys().next_back() {
+                Some(id) => id.clone(),
+                None => break,
+            };
+            if let Some(entry) = self.entries.get(&id) {
+                self.write(id.0, &id.1, entry)?;
+            }
+            self.entries.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Writes an accumulator to the RawStore if it has been modified
+    #[inline]
+    fn write(&self, window: Window, raw_key: &[u8], entry: &Entry<A>) -> Result<()> {
+        if entry.modified.get() {
+            self.raw_store.borrow_mut().put_bytes(
+                &self.namespace,
+                &window_key(window, raw_key),
+                &entry.value.into_raw()?,
+            )?;
+            entry.modified.set(false);
+            entry.stored.set(true);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the RawStore key of a (key, window) pair
+///
+/// The window end comes first so that windows are stored in the order they close.
+#[inline]
+fn window_key(window: Window, raw_key: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16 + raw_key.len());
+    window.end.encode_ordered(&mut key);
+    window.start.encode_ordered(&mut key);
+    key.extend_from_slice(raw_key);
+    key
+}
+
+#[inline]
+fn decode_window_key(key: &[u8]) -> Result<(Window, Vec<u8>)> {
+    if key.len() < 16 {
+        return Err(BrittMarieError::Serde(format!(
+            "expected at least 16 window key bytes, found {}",
+            key.len()
+        )));
+    }
+    let end = u64::decode_ordered(&key[..8])?;
+    let start = u64::decode_ordered(&key[8..16])?;
+    Ok((Window::new(start, end), key[16..].to_vec()))
+}
+
+impl<K, A> IndexOps for WindowIndex<K, A>
+where
+    K: Key + Eq + Hash,
+    A: Value,
+{
+    fn persist(&self) -> Result<()> {
+        if self.mode.is_lazy() {
+            let mut records = Vec::new();
+            for ((window, raw_key), entry) in self.entries.iter() {
+                if entry.modified.get() {
+                    records.push((window_key(*window, raw_key), entry.value.into_raw()?));
+                }
+            }
+            if records.is_empty() {
+                return Ok(());
+            }
+            self.raw_store
+                .borrow_mut()
+                .put_bytes_batch(&self.namespace, records)?;
+            for entry in self.entries.values().filter(|e| e.modified.get()) {
+                entry.modified.set(false);
+                entry.stored.set(true);
+            }
+        }
+        // Else just ignore as COW copies on each modification
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn raw_store() -> (tempfile::TempDir, Rc<RefCell<RawStore>>) {
+        let temp_dir = tempdir().unwrap();
+        let raw_store = Rc::new(RefCell::new(RawStore::new(
+            temp_dir.path().to_str().unwrap(),
+        )));
+        (temp_dir, raw_store)
+    }
+
+    #[test]
+    fn tumbling_test() {
+        let (_dir, raw_store) = raw_store();
+        let assigner = WindowAssigner::Tumbling { size: 10 };
+        let mut index: WindowIndex<u64, u64> =
+            WindowIndex::new("counts", assigner, 4, raw_store.clone());
+        // Spread the windows over memory and disk
+        for ts in 0..100 {
+            for key in 0..3 {
+                assert!(index.insert(key, ts, |count| *count += 1).unwrap());
+            }
+        }
+        assert_eq!(index.len(), 30);
+
+        let fired = index.advance_watermark(20).unwrap();
+        assert_eq!(fired.len(), 6);
+        assert_eq!(fired[0].1, Window::new(0, 10));
+        assert!(fired.iter().all(|(_, _, count)| *count == 10));
+        // Late events are dropped
+        assert!(!index.insert(0, 15, |count| *count += 1).unwrap());
+
+        index.persist().unwrap();
+        let mut reopened: WindowIndex<u64, u64> =
+            WindowIndex::open("counts", assigner, 4, raw_store).unwrap();
+        assert_eq!(reopened.len(), 24);
+        assert_eq!(reopened.watermark(), 20);
+        assert!(!reopened.insert(0, 15, |count| *count += 1).unwrap());
+        let fired = reopened.advance_watermark(u64::MAX).unwrap();
+        assert_eq!(fired.len(), 24);
+        assert!(fired.iter().all(|(_, _, count)| *count == 10));
+        assert!(reopened.is_empty());
+        assert!(reopened.advance_watermark(u64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sliding_test() {
+        let (_dir, raw_store) = raw_store();
+        let assigner = WindowAssigner::Sliding { size: 10, slide: 5 };
+        assert_eq!(
+            assigner.assign(12),
+            vec![Window::new(5, 15), Window::new(10, 20)]
+        );
+        assert_eq!(assigner.assign(3), vec![Window::new(0, 10)]);
+        // Events between two windows belong to none
+        let gaps = WindowAssigner::Sliding { size: 5, slide: 10 };
+        assert_eq!(gaps.assign(13), vec![Window::new(10, 15)]);
+        assert!(gaps.assign(17).is_empty());
+
+        let mut index: WindowIndex<u64, u64> = WindowIndex::cow("sums", assigner, 2, raw_store);
+        for ts in 0..20 {
+            index.insert(1, ts, |sum| *sum += ts).unwrap();
+        }
+        let fired = index.advance_watermark(15).unwrap();
+        let sums: Vec<(Window, u64)> = fired.into_iter().map(|(_, w, s)| (w, s)).collect();
+        assert_eq!(
+            sums,
+            vec![(Window::new(0, 10), 45), (Window::new(5, 15), 95)]
+        );
+    }
+
+    #[test]
+    fn session_test() {
+        let (_dir, raw_store) = raw_store();
+        let mut index: WindowIndex<String, u64> =
+            WindowIndex::session("sessions", 10, |count, other| *count += other, 1, raw_store);
+        let user = String::from("user");
+        for &ts in [0, 5, 30, 12].iter() {
+            index.insert(user.clone(), ts, |count| *count += 1).unwrap();
+        }
+        assert_eq!(index.len(), 2);
+        // Bridges the two sessions
+        index.insert(user.clone(), 21, |count| *count += 1).unwrap();
+        assert_eq!(index.len(), 1);
+
+        assert!(index.advance_watermark(39).unwrap().is_empty());
+        let fired = index.advance_watermark(40).unwrap();
+        assert_eq!(fired, vec![(user, Window::new(0, 40), 5)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "session windows need a merge function")]
+    fn session_merge_test() {
+        let (_dir, raw_store) = raw_store();
+        let assigner = WindowAssigner::Session { gap: 10 };
+        let _index: WindowIndex<u64, u64> = WindowIndex::new("sessions", assigner, 1, raw_store);
+    }
+}
@@ -6,7 +6,7 @@
 //!
 //!
 //!```text
-//!     ValueIndex   HashIndex   ValueIndex
+//!     ValueIndex   HashIndex   OrderedIndex
 //!          \           |           /
 //!           \          |          /
 //!            \         |         /
@@ -41,12 +41,35 @@ mod raw_store;
 
 pub use crate::error::BrittMarieError;
 pub use crate::index::{
-    hash::HashIndex, value::ValueIndex, HashOps, IndexOps, OrderedOps, ValueOps,
+    aggregate::{AggregateIndex, Aggregator, Avg, AvgPartial, Count, Max, Min, Sum},
+    hash::{
+        DefaultHashBuilder, Entry, HashIndex, Iter, Keys, OccupiedEntry, RandomHashBuilder,
+        TryIter, VacantEntry, ValueMut, Values,
+    },
+    list::ListIndex,
+    multimap::MultiMapIndex,
+    ordered::{OrderedIndex, Range, TryRange},
+    set::SetIndex,
+    timer::TimerIndex,
+    value::ValueIndex,
+    window::{Window, WindowIndex},
+    DecodeMode, EvictionPolicy, HashOps, IndexOps, ListOps, OrderedOps, ResizePolicy, ValueOps,
+    WindowAssigner,
 };
+pub use crate::raw_store::cipher::{KeyProvider, KeyRing, ValueCipher};
+#[cfg(feature = "encryption")]
+pub use crate::raw_store::cipher::XChaChaCipher;
 pub use crate::raw_store::RawStore;
+#[cfg(feature = "compression")]
+pub use crate::raw_store::compression::Compression;
+pub use crate::raw_store::memory::MemoryManager;
 
 #[cfg(feature = "britt-marie-derive")]
 extern crate britt_marie_derive;
 #[cfg(feature = "britt-marie-derive")]
 #[doc(hidden)]
 pub use britt_marie_derive::*;
+
+// Used by code generated through `#[derive(BrittMarieData)]`
+#[doc(hidden)]
+pub use prost;
@@ -0,0 +1,198 @@
+use crate::error::*;
+use fxhash::FxHashMap;
+
+/// Size of the keys handed out by a [KeyProvider]
+pub const KEY_LEN: usize = 32;
+
+/// Source of the key material used by a [ValueCipher]
+///
+/// Keys are identified by an id that is stored next to every encrypted value, so
+/// values written before a key rotation remain readable as long as the provider
+/// still hands out the retired key.
+pub trait KeyProvider {
+    /// Id of the key that new values are encrypted with
+    fn current_key_id(&self) -> u32;
+    /// Returns the key with the given id, if known
+    fn key(&self, id: u32) -> Option<[u8; KEY_LEN]>;
+}
+
+/// Authenticated encryption of values written to the RawStore
+///
+/// `aad` is the backend key of the record, which binds a ciphertext to the
+/// record it was written for.
+pub trait ValueCipher {
+    /// Encrypts an encoded value
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>>;
+    /// Decrypts and authenticates a value produced by [ValueCipher::encrypt]
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>>;
+    /// Whether the ciphertext was written with a key other than the current one
+    fn needs_reencrypt(&self, ciphertext: &[u8]) -> bool;
+}
+
+/// An in-memory [KeyProvider]
+///
+/// ```
+/// use britt_marie::KeyRing;
+///
+/// let mut key_ring = KeyRing::new(1, [7u8; 32]);
+/// key_ring.rotate(2, [9u8; 32]);
+/// ```
+#[derive(Clone, Default)]
+pub struct KeyRing {
+    keys: FxHashMap<u32, [u8; KEY_LEN]>,
+    current: u32,
+}
+
+impl KeyRing {
+    /// Creates a KeyRing with a single active key
+    pub fn new(id: u32, key: [u8; KEY_LEN]) -> KeyRing {
+        let mut keys = FxHashMap::default();
+        keys.insert(id, key);
+        KeyRing { keys, current: id }
+    }
+
+    /// Makes `key` the key for new writes while keeping older keys for reads
+    pub fn rotate(&mut self, id: u32, key: [u8; KEY_LEN]) {
+        self.keys.insert(id, key);
+        self.current = id;
+    }
+
+    /// Drops a retired key
+    ///
+    /// Values still encrypted with it can no longer be read, see [RawStore::reencrypt].
+    ///
+    /// [RawStore::reencrypt]: crate::RawStore::reencrypt
+    pub fn retire(&mut self, id: u32) {
+        assert_ne!(id, self.current, "cannot retire the current key");
+        self.keys.remove(&id);
+    }
+}
+
+impl KeyProvider for KeyRing {
+    #[inline]
+    fn current_key_id(&self) -> u32 {
+        self.current
+    }
+    #[inline]
+    fn key(&self, id: u32) -> Option<[u8; KEY_LEN]> {
+        self.keys.get(&id).copied()
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod xchacha {
+    use super::*;
+    use chacha20poly1305::aead::{Aead, NewAead, Payload};
+    use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+    /// Length of the key id header
+    const KEY_ID_LEN: usize = 4;
+    /// Length of the random XChaCha20 nonce
+    const NONCE_LEN: usize = 24;
+
+    /// XChaCha20-Poly1305 [ValueCipher]
+    ///
+    /// Ciphertexts are laid out as `key id | nonce | encrypted value | tag`. The
+    /// extended nonce is large enough to be picked at random for every write.
+    pub struct XChaChaCipher<P: KeyProvider> {
+        provider: P,
+    }
+
+    impl<P: KeyProvider> XChaChaCipher<P> {
+        pub fn new(provider: P) -> Self {
+            XChaChaCipher { provider }
+        }
+
+        #[inline]
+        fn cipher(&self, id: u32) -> Result<XChaCha20Poly1305> {
+            let key = self
+                .provider
+                .key(id)
+                .ok_or_else(|| BrittMarieError::Cipher(format!("unknown key id {}", id)))?;
+            Ok(XChaCha20Poly1305::new(&Key::from(key)))
+        }
+    }
+
+    impl<P: KeyProvider> ValueCipher for XChaChaCipher<P> {
+        fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+            let id = self.provider.current_key_id();
+            let mut nonce = [0u8; NONCE_LEN];
+            getrandom::getrandom(&mut nonce).map_err(|e| BrittMarieError::Cipher(e.to_string()))?;
+            let sealed = self
+                .cipher(id)?
+                .encrypt(
+                    &XNonce::from(nonce),
+                    Payload {
+                        msg: plaintext,
+                        aad,
+                    },
+                )
+                .map_err(|_| BrittMarieError::Cipher(String::from("encryption failed")))?;
+
+            let mut buf = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + sealed.len());
+            buf.extend_from_slice(&id.to_be_bytes());
+            buf.extend_from_slice(&nonce);
+            buf.extend_from_slice(&sealed);
+            Ok(buf)
+        }
+
+        fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+            let id = key_id(ciphertext)?;
+            let mut nonce = [0u8; NONCE_LEN];
+            nonce.copy_from_slice(&ciphertext[KEY_ID_LEN..KEY_ID_LEN + NONCE_LEN]);
+            self.cipher(id)?
+                .decrypt(
+                    &XNonce::from(nonce),
+                    Payload {
+                        msg: &ciphertext[KEY_ID_LEN + NONCE_LEN..],
+                        aad,
+                    },
+                )
+                .map_err(|_| BrittMarieError::Cipher(String::from("decryption failed")))
+        }
+
+        fn needs_reencrypt(&self, ciphertext: &[u8]) -> bool {
+            matches!(key_id(ciphertext), Ok(id) if id != self.provider.current_key_id())
+        }
+    }
+
+    #[inline]
+    fn key_id(ciphertext: &[u8]) -> Result<u32> {
+        if ciphertext.len() < KEY_ID_LEN + NONCE_LEN {
+            return Err(BrittMarieError::Cipher(String::from(
+                "truncated ciphertext",
+            )));
+        }
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&ciphertext[..KEY_ID_LEN]);
+        Ok(u32::from_be_bytes(id))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn rotation_test() {
+            let mut cipher = XChaChaCipher::new(KeyRing::new(1, [1u8; KEY_LEN]));
+            let sealed = cipher.encrypt(b"britt-marie", b"key").unwrap();
+            assert!(!sealed.windows(11).any(|w| w == b"britt-marie"));
+            assert_eq!(cipher.decrypt(&sealed, b"key").unwrap(), b"britt-marie");
+            // The ciphertext is bound to its record
+            assert!(cipher.decrypt(&sealed, b"other").is_err());
+
+            cipher.provider.rotate(2, [2u8; KEY_LEN]);
+            assert!(cipher.needs_reencrypt(&sealed));
+            assert_eq!(cipher.decrypt(&sealed, b"key").unwrap(), b"britt-marie");
+            let resealed = cipher.encrypt(b"britt-marie", b"key").unwrap();
+            assert!(!cipher.needs_reencrypt(&resealed));
+
+            cipher.provider.retire(1);
+            assert!(cipher.decrypt(&sealed, b"key").is_err());
+            assert_eq!(cipher.decrypt(&resealed, b"key").unwrap(), b"britt-marie");
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+pub use self::xchacha::XChaChaCipher;
@@ -0,0 +1,183 @@
+use crate::error::*;
+use std::io::{Read, Write};
+
+/// Header byte of values that are stored as is
+const STORED: u8 = 0;
+/// Header byte of LZ4 compressed values
+const LZ4: u8 = 1;
+/// Header byte of Zstd compressed values
+const ZSTD: u8 = 2;
+
+/// Value compression that may be enabled for a RawStore namespace
+///
+/// Compressed values are prefixed with a single header byte that identifies the codec,
+/// so a namespace may switch between codecs without rewriting existing data. Values that
+/// do not shrink are stored uncompressed.
+///
+/// As every value needs a header, compression can only be enabled on namespaces that
+/// do not hold uncompressed values, see [RawStore::set_compression].
+///
+/// [RawStore::set_compression]: crate::RawStore::set_compression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Compression {
+    /// LZ4 block compression, favours speed over ratio
+    Lz4,
+    /// Zstd frame compression
+    Zstd {
+        /// Compression level, `0` selects the zstd default
+        level: i32,
+        /// Optional dictionary, see [Compression::train_dictionary]
+        ///
+        /// Values written with a dictionary can only be read back with the same dictionary.
+        dictionary: Option<Vec<u8>>,
+    },
+}
+
+impl Compression {
+    /// Zstd compression without a dictionary
+    pub fn zstd(level: i32) -> Self {
+        Compression::Zstd {
+            level,
+            dictionary: None,
+        }
+    }
+
+    /// Zstd compression using a trained dictionary
+    pub fn zstd_with_dictionary(level: i32, dictionary: Vec<u8>) -> Self {
+        Compression::Zstd {
+            level,
+            dictionary: Some(dictionary),
+        }
+    }
+
+    /// Trains a Zstd dictionary of at most `max_size` bytes from encoded sample values
+    ///
+    /// Dictionaries pay off for namespaces with many small values that share structure,
+    /// where each value on its own is too small to compress well.
+    pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Vec<u8>> {
+        zstd::dict::from_samples(samples, max_size)
+            .map_err(|e| BrittMarieError::Compression(e.to_string()))
+    }
+
+    /// Compresses an encoded value and prefixes it with the codec header
+    pub(crate) fn compress(&self, raw: &[u8]) -> Result<Vec<u8>> {
+        let (header, compressed) = match self {
+            Compression::Lz4 => (LZ4, lz4_flex::compress_prepend_size(raw)),
+            Compression::Zstd { level, dictionary } => {
+                let dictionary = dictionary.as_deref().unwrap_or(&[]);
+                let mut encoder =
+                    zstd::stream::write::Encoder::with_dictionary(Vec::new(), *level, dictionary)
+                        .map_err(compression_err)?;
+                encoder.write_all(raw).map_err(compression_err)?;
+                (ZSTD, encoder.finish().map_err(compression_err)?)
+            }
+        };
+
+        let mut buf;
+        if compressed.len() < raw.len() {
+            buf = Vec::with_capacity(compressed.len() + 1);
+            buf.push(header);
+            buf.extend_from_slice(&compressed);
+        } else {
+            buf = Vec::with_capacity(raw.len() + 1);
+            buf.push(STORED);
+            buf.extend_from_slice(raw);
+        }
+        Ok(buf)
+    }
+
+    /// Restores an encoded value written by [Compression::compress]
+    ///
+    /// The header decides which codec is used, `self` only provides the dictionary.
+    pub(crate) fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let (header, body) = match bytes.split_first() {
+            Some((header, body)) if is_header(*header) => (header, body),
+            _ => {
+                return Err(BrittMarieError::Compression(String::from(
+                    "value without a compression header",
+                )))
+            }
+        };
+
+        match *header {
+            STORED => Ok(body.to_vec()),
+            LZ4 => lz4_flex::decompress_size_prepended(body)
+                .map_err(|e| BrittMarieError::Compression(e.to_string())),
+            ZSTD => {
+                let dictionary = match self {
+                    Compression::Zstd {
+                        dictionary: Some(dictionary),
+                        ..
+                    } => dictionary.as_slice(),
+                    _ => &[],
+                };
+                let mut decoder = zstd::stream::read::Decoder::with_dictionary(body, dictionary)
+                    .map_err(compression_err)?;
+                let mut buf = Vec::new();
+                decoder.read_to_end(&mut buf).map_err(compression_err)?;
+                Ok(buf)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Returns whether the first byte of a stored value is a compression header
+///
+/// Encoded values start with a field tag, whose field number is never zero.
+/// Tags with a field number of zero take up the bytes 0 to 7.
+#[inline]
+fn is_header(byte: u8) -> bool {
+    byte == STORED || byte == LZ4 || byte == ZSTD
+}
+
+#[inline]
+fn compression_err(e: std::io::Error) -> BrittMarieError {
+    BrittMarieError::Compression(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Value;
+
+    #[test]
+    fn roundtrip_test() {
+        let raw: Vec<u8> = (0..4096).map(|i| (i % 16) as u8).collect();
+        let samples: Vec<Vec<u8>> = (0..256)
+            .map(|i| format!("{{\"sensor\": \"temperature\", \"id\": {}}}", i).into_bytes())
+            .collect();
+        let dictionary = Compression::train_dictionary(&samples, 1024).unwrap();
+
+        for codec in vec![
+            Compression::Lz4,
+            Compression::zstd(0),
+            Compression::zstd_with_dictionary(3, dictionary),
+        ] {
+            let compressed = codec.compress(&raw).unwrap();
+            assert!(compressed.len() < raw.len());
+            assert_eq!(codec.decompress(&compressed).unwrap(), raw);
+
+            // Values that do not shrink are stored as is
+            let stored = codec.compress(&[1, 2, 3]).unwrap();
+            assert_eq!(stored, vec![STORED, 1, 2, 3]);
+            assert_eq!(codec.decompress(&stored).unwrap(), vec![1, 2, 3]);
+        }
+
+        // The header and not the configured codec decides how to decompress
+        let compressed = Compression::Lz4.compress(&raw).unwrap();
+        assert_eq!(Compression::zstd(1).decompress(&compressed).unwrap(), raw);
+
+        // Raw records that start with a header byte keep it
+        let meta = [0u8; 16];
+        let stored = Compression::Lz4.compress(&meta).unwrap();
+        assert_eq!(Compression::Lz4.decompress(&stored).unwrap(), meta);
+        let empty = Compression::Lz4.compress(&[]).unwrap();
+        assert!(Compression::Lz4.decompress(&empty).unwrap().is_empty());
+
+        // Values without a header were not written by a compressed namespace
+        let encoded = 300u64.into_raw().unwrap();
+        assert!(Compression::Lz4.decompress(&encoded).is_err());
+        assert!(Compression::Lz4.decompress(&[]).is_err());
+    }
+}
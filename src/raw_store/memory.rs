@@ -0,0 +1,156 @@
+use std::cell::Cell;
+use std::rc::{Rc, Weak};
+
+/// Total accesses after which the access counts of all indexes are halved
+const ACCESS_DECAY_PERIOD: u64 = 4096;
+
+/// Memory accounting of a single index, shared with the [MemoryManager]
+pub(crate) struct MemoryShare {
+    namespace: Vec<u8>,
+    /// Estimated bytes held in memory
+    bytes: Cell<usize>,
+    /// Accesses, decayed over time
+    accesses: Cell<u64>,
+    /// Bytes the index has been asked to shrink to
+    target: Cell<Option<usize>>,
+}
+
+impl MemoryShare {
+    #[inline]
+    pub fn bytes(&self) -> usize {
+        self.bytes.get()
+    }
+
+    #[inline]
+    pub fn set_bytes(&self, bytes: usize) {
+        self.bytes.set(bytes);
+    }
+
+    #[inline(always)]
+    pub fn record_access(&self) {
+        self.accesses.set(self.accesses.get() + 1);
+    }
+
+    /// Takes the size the index has been asked to shrink to, if any
+    #[inline]
+    pub fn take_target(&self) -> Option<usize> {
+        self.target.take()
+    }
+}
+
+/// Global memory budget of the indexes that share a RawStore
+///
+/// Indexes report their estimated memory usage as they are updated. Once the total
+/// exceeds the budget, the indexes with the fewest recent accesses are asked to
+/// shrink, which they do on their next update or on
+/// [IndexOps::enforce_budget](crate::IndexOps::enforce_budget). The manager cannot
+/// shrink an index by itself, as references returned by reads may still point into
+/// its table. Estimates cover the in-memory tables and encoded values, not heap
+/// memory owned by decoded values.
+pub struct MemoryManager {
+    budget: usize,
+    shares: Vec<Weak<MemoryShare>>,
+}
+
+impl MemoryManager {
+    pub fn new(budget: usize) -> Self {
+        MemoryManager {
+            budget,
+            shares: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Returns the estimated bytes held by all registered indexes
+    pub fn used(&self) -> usize {
+        self.live_shares().map(|share| share.bytes.get()).sum()
+    }
+
+    /// Returns the estimated bytes held by each registered index, by namespace
+    pub fn usage(&self) -> Vec<(Vec<u8>, usize)> {
+        self.live_shares()
+            .map(|share| (share.namespace.clone(), share.bytes.get()))
+            .collect()
+    }
+
+    #[inline]
+    pub fn is_exceeded(&self) -> bool {
+        self.used() > self.budget
+    }
+
+    /// Registers an index that holds `bytes` in memory
+    pub(crate) fn register(&mut self, namespace: &[u8], bytes: usize) -> Rc<MemoryShare> {
+        // Forget about indexes that have been dropped
+        self.shares.retain(|share| share.strong_count() > 0);
+        let share = Rc::new(MemoryShare {
+            namespace: namespace.to_vec(),
+            bytes: Cell::new(bytes),
+            accesses: Cell::new(0),
+            target: Cell::new(None),
+        });
+        self.shares.push(Rc::downgrade(&share));
+        share
+    }
+
+    /// Asks the coldest indexes to shrink until the estimated usage fits the budget
+    ///
+    /// An index gives up at most half of its memory per rebalance, so that the
+    /// excess is spread over several indexes if the coldest one is small. Targets
+    /// of earlier rebalances that have not been acted on yet are replaced.
+    pub(crate) fn rebalance(&mut self) {
+        let mut shares: Vec<Rc<MemoryShare>> = self.live_shares().collect();
+        let used: usize = shares.iter().map(|share| share.bytes.get()).sum();
+        let mut excess = used.saturating_sub(self.budget);
+        shares.sort_by_key(|share| share.accesses.get());
+        for share in shares.iter() {
+            let bytes = share.bytes.get();
+            let reclaim = usize::min(excess, bytes / 2);
+            if reclaim > 0 {
+                share.target.set(Some(bytes - reclaim));
+                excess -= reclaim;
+            } else {
+                share.target.set(None);
+            }
+        }
+
+        // Only recent accesses count
+        let accesses: u64 = shares.iter().map(|share| share.accesses.get()).sum();
+        if accesses > ACCESS_DECAY_PERIOD {
+            for share in shares.iter() {
+                share.accesses.set(share.accesses.get() / 2);
+            }
+        }
+    }
+
+    #[inline]
+    fn live_shares(&self) -> impl Iterator<Item = Rc<MemoryShare>> + '_ {
+        self.shares.iter().filter_map(Weak::upgrade)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebalance_test() {
+        let mut manager = MemoryManager::new(1000);
+        let cold = manager.register(b"cold", 600);
+        let hot = manager.register(b"hot", 600);
+        hot.record_access();
+        assert!(manager.is_exceeded());
+
+        manager.rebalance();
+        assert_eq!(cold.take_target(), Some(400));
+        assert_eq!(hot.take_target(), None);
+
+        // Dropped indexes no longer count
+        drop(cold);
+        assert_eq!(manager.used(), 600);
+        assert_eq!(manager.usage(), vec![(b"hot".to_vec(), 600)]);
+    }
+}
@@ -14,8 +14,100 @@ cfg_if::cfg_if! {
 
 use backend::Backend;
 
+/// Value compression for RawStore namespaces
+#[cfg(feature = "compression")]
+pub mod compression;
+
+#[cfg(feature = "compression")]
+use self::compression::Compression;
+#[cfg(feature = "compression")]
+use fxhash::FxHashMap;
+use std::borrow::Cow;
+
+/// Encryption of stored values
+pub mod cipher;
+
+use self::cipher::ValueCipher;
+
+/// Memory budget shared by the indexes of a RawStore
+pub mod memory;
+
+use self::memory::{MemoryManager, MemoryShare};
+use std::rc::Rc;
+
+/// Batched scans over a namespace
+pub(crate) mod scan;
+
+/// Number of values rewritten per batch by [RawStore::reencrypt]
+const REENCRYPT_BATCH_SIZE: usize = 1024;
+
+/// Namespace of the records that mark which namespaces are compressed
+///
+/// A namespace is marked once compression is enabled on it, so that it can be
+/// enabled again after a restart even though the namespace holds data.
+#[cfg(feature = "compression")]
+const COMPRESSED_NAMESPACES: &[u8] = b"_britt_marie_compressed";
+
+/// Leading byte of values that went through the cipher
+///
+/// Values written before [RawStore::set_cipher] are stored without it. The byte
+/// carries protobuf wire type 7, which does not exist, and is none of the
+/// compression headers, so it never starts a plaintext value.
+const ENCRYPTED: u8 = 0x07;
+
+/// Builds the key under which a record of a namespace is stored in the backend
+///
+/// The namespace is prefixed with its length so that a namespace can never be
+/// a prefix of another one.
+#[inline]
+fn namespaced_key<K: Key>(namespace: &[u8], key: &K) -> Result<Vec<u8>> {
+    let mut raw_key = namespace_prefix(namespace, key.encoded_len());
+    key.encode(&mut raw_key)
+        .map_err(|e| BrittMarieError::Serde(e.to_string()))?;
+    Ok(raw_key)
+}
+
+/// Same as [namespaced_key] but for keys that are already encoded
+#[inline]
+fn namespaced_bytes(namespace: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut raw_key = namespace_prefix(namespace, key.len());
+    raw_key.extend_from_slice(key);
+    raw_key
+}
+
+/// Returns the common prefix of all backend keys of a namespace
+#[inline]
+fn namespace_prefix(namespace: &[u8], key_len: usize) -> Vec<u8> {
+    assert!(namespace.len() <= u16::MAX as usize, "namespace too long");
+    let mut prefix = Vec::with_capacity(2 + namespace.len() + key_len);
+    prefix.extend_from_slice(&(namespace.len() as u16).to_be_bytes());
+    prefix.extend_from_slice(namespace);
+    prefix
+}
+
+/// Encrypts a value and prefixes it with the [ENCRYPTED] marker
+#[inline]
+fn seal(cipher: &dyn ValueCipher, plaintext: &[u8], raw_key: &[u8]) -> Result<Vec<u8>> {
+    let sealed = cipher.encrypt(plaintext, raw_key)?;
+    let mut value = Vec::with_capacity(1 + sealed.len());
+    value.push(ENCRYPTED);
+    value.extend_from_slice(&sealed);
+    Ok(value)
+}
+
+/// The durable layer shared by all indexes
+///
+/// Each index writes its records under a namespace of its own, e.g., the key of a
+/// ValueIndex, so indexes may use overlapping keys without clashing.
 pub struct RawStore {
     backend: Backend,
+    /// Value compression enabled per namespace
+    #[cfg(feature = "compression")]
+    compression: FxHashMap<Vec<u8>, Compression>,
+    /// Cipher applied to all values
+    cipher: Option<Box<dyn ValueCipher>>,
+    /// Memory budget of the indexes on top of the store
+    memory: Option<MemoryManager>,
 }
 
 impl RawStore {
@@ -23,40 +115,216 @@ impl RawStore {
     pub fn new(path: &str) -> RawStore {
         Self {
             backend: Backend::new(Path::new(path)),
+            #[cfg(feature = "compression")]
+            compression: FxHashMap::default(),
+            cipher: None,
+            memory: None,
+        }
+    }
+
+    /// Enables value compression for a namespace
+    ///
+    /// Every value of a compressed namespace carries a codec header, so compression
+    /// cannot be turned on for a namespace that already holds uncompressed values.
+    /// Such namespaces are rejected with [BrittMarieError::Compression]. Namespaces
+    /// that were compressed before, e.g., prior to a restart, are accepted.
+    #[cfg(feature = "compression")]
+    pub fn set_compression<N>(&mut self, namespace: N, compression: Compression) -> Result<()>
+    where
+        N: Into<Vec<u8>>,
+    {
+        let namespace = namespace.into();
+        if self.get_bytes(COMPRESSED_NAMESPACES, &namespace)?.is_none() {
+            if !self.scan_keys(&namespace, &[], 1)?.is_empty() {
+                return Err(BrittMarieError::Compression(format!(
+                    "namespace `{}` holds uncompressed values",
+                    String::from_utf8_lossy(&namespace)
+                )));
+            }
+            self.put_bytes(COMPRESSED_NAMESPACES, &namespace, &[])?;
+        }
+        self.compression.insert(namespace, compression);
+        Ok(())
+    }
+
+    /// Sets the cipher that values are encrypted with before they reach the backend
+    ///
+    /// Only values are encrypted. Keys and namespaces are stored in plaintext, so
+    /// identifiers that must not be readable at rest should not be used as keys
+    /// as is, e.g., hash them with a keyed hash first.
+    ///
+    /// Values written before the cipher was set stay readable and are encrypted
+    /// when rewritten or by [RawStore::reencrypt].
+    pub fn set_cipher<C>(&mut self, cipher: C)
+    where
+        C: ValueCipher + 'static,
+    {
+        self.cipher = Some(Box::new(cipher));
+    }
+
+    /// Caps the estimated memory used by the HashIndexes on top of the store
+    ///
+    /// Must be set before the indexes are created, as only indexes created
+    /// afterwards are accounted for.
+    pub fn set_memory_budget(&mut self, budget: usize) {
+        self.memory = Some(MemoryManager::new(budget));
+    }
+
+    /// Returns the [MemoryManager] of the store, if a budget has been set
+    #[inline]
+    pub fn memory_manager(&self) -> Option<&MemoryManager> {
+        self.memory.as_ref()
+    }
+
+    /// Registers an index with the [MemoryManager], if any
+    #[inline]
+    pub(crate) fn register_memory(
+        &mut self,
+        namespace: &[u8],
+        bytes: usize,
+    ) -> Option<Rc<MemoryShare>> {
+        self.memory
+            .as_mut()
+            .map(|memory| memory.register(namespace, bytes))
+    }
+
+    /// Asks the coldest indexes to shrink if the memory budget is exceeded
+    #[inline]
+    pub(crate) fn rebalance_memory(&mut self) {
+        if let Some(memory) = self.memory.as_mut() {
+            if memory.is_exceeded() {
+                memory.rebalance();
+            }
+        }
+    }
+
+    /// Rewrites values that are encrypted with a retired key using the current key
+    ///
+    /// Values written before [RawStore::set_cipher] are encrypted as well. Returns
+    /// the number of rewritten values. Once done, retired keys may be dropped from
+    /// the [KeyProvider](cipher::KeyProvider).
+    pub fn reencrypt(&mut self) -> Result<usize> {
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => return Ok(0),
+        };
+        let mut total = 0;
+        let mut batch = Vec::with_capacity(REENCRYPT_BATCH_SIZE);
+        for record in self.backend.iter() {
+            let (raw_key, value) = record?;
+            let plaintext = match value.split_first() {
+                Some((&ENCRYPTED, ciphertext)) if cipher.needs_reencrypt(ciphertext) => {
+                    cipher.decrypt(ciphertext, &raw_key)?
+                }
+                Some((&ENCRYPTED, _)) => continue,
+                _ => value.into_vec(),
+            };
+            let sealed = seal(cipher.as_ref(), &plaintext, &raw_key)?;
+            batch.push((raw_key, sealed));
+            if batch.len() == REENCRYPT_BATCH_SIZE {
+                total += batch.len();
+                self.backend.put_batch(batch.drain(..))?;
+            }
         }
+        if !batch.is_empty() {
+            total += batch.len();
+            self.backend.put_batch(batch)?;
+        }
+        Ok(total)
+    }
+
+    /// Applies the value codec and cipher of a namespace to an encoded value
+    #[inline]
+    fn encode_value<'a>(
+        &self,
+        namespace: &[u8],
+        raw_key: &[u8],
+        raw_value: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>> {
+        let mut value = Cow::Borrowed(raw_value);
+        #[cfg(feature = "compression")]
+        {
+            if let Some(compression) = self.compression.get(namespace) {
+                value = Cow::Owned(compression.compress(&value)?);
+            }
+        }
+        #[cfg(not(feature = "compression"))]
+        let _ = namespace;
+        if let Some(cipher) = &self.cipher {
+            value = Cow::Owned(seal(cipher.as_ref(), &value, raw_key)?);
+        }
+        Ok(value)
+    }
+
+    /// Reverts [RawStore::encode_value] on a value read from the backend
+    #[inline]
+    fn decode_value(&self, namespace: &[u8], raw_key: &[u8], bytes: Vec<u8>) -> Result<Vec<u8>> {
+        let mut value = bytes;
+        if let Some(cipher) = &self.cipher {
+            if let Some((&ENCRYPTED, ciphertext)) = value.split_first() {
+                value = cipher.decrypt(ciphertext, raw_key)?;
+            }
+        }
+        #[cfg(feature = "compression")]
+        {
+            if let Some(compression) = self.compression.get(namespace) {
+                value = compression.decompress(&value)?;
+            }
+        }
+        #[cfg(not(feature = "compression"))]
+        let _ = namespace;
+        Ok(value)
     }
 
     /// Insert a single Key-Value record into the store
     #[inline]
-    pub fn put<K, V>(&mut self, key: &K, value: &V) -> Result<()>
+    pub fn put<K, V>(&mut self, namespace: &[u8], key: &K, value: &V) -> Result<()>
     where
         K: Key,
         V: Value,
     {
-        let raw_key = key.into_raw()?;
         let raw_value = value.into_raw()?;
-        self.backend.put(raw_key, raw_value)
+        self.put_raw(namespace, key, &raw_value)
     }
 
     /// Insert a batch of Key-Values into the store
     #[inline]
-    pub fn put_batch<K, V, I>(&mut self, kv_pairs: I) -> Result<()>
+    pub fn put_batch<K, V, I>(&mut self, namespace: &[u8], kv_pairs: I) -> Result<()>
     where
         K: Key,
         V: Value,
         I: Iterator<Item = (K, V)>,
     {
-        self.backend.put_batch(kv_pairs)
+        let mut batch = Vec::new();
+        for (key, value) in kv_pairs {
+            let raw_key = namespaced_key(namespace, &key)?;
+            let raw_value = value.into_raw()?;
+            let raw_value = self
+                .encode_value(namespace, &raw_key, &raw_value)?
+                .into_owned();
+            batch.push((raw_key, raw_value));
+        }
+        self.backend.put_batch(batch)
+    }
+
+    /// Insert a Key with an already encoded Value into the store
+    #[inline]
+    pub fn put_raw<K>(&mut self, namespace: &[u8], key: &K, raw_value: &[u8]) -> Result<()>
+    where
+        K: Key,
+    {
+        let raw_key = namespaced_key(namespace, key)?;
+        let raw_value = self.encode_value(namespace, &raw_key, raw_value)?;
+        self.backend.put(raw_key, raw_value)
     }
 
     #[inline]
-    pub fn get<K, V>(&self, key: &K) -> Result<Option<V>>
+    pub fn get<K, V>(&self, namespace: &[u8], key: &K) -> Result<Option<V>>
     where
         K: Key,
         V: Value,
     {
-        let raw_key = key.into_raw()?;
-        let raw_opt = self.backend.get(raw_key)?;
+        let raw_opt = self.get_raw(namespace, key)?;
         if let Some(raw) = raw_opt {
             let v = V::from_raw(&raw)?;
             Ok(Some(v))
@@ -64,6 +332,167 @@ impl RawStore {
             Ok(None)
         }
     }
+
+    /// Fetch the encoded Value of a Key without decoding it
+    #[inline]
+    pub fn get_raw<K>(&self, namespace: &[u8], key: &K) -> Result<Option<Vec<u8>>>
+    where
+        K: Key,
+    {
+        let raw_key = namespaced_key(namespace, key)?;
+        self.get_namespaced(namespace, &raw_key)
+    }
+
+    /// Delete a single record from the store
+    #[inline]
+    pub fn delete<K>(&mut self, namespace: &[u8], key: &K) -> Result<()>
+    where
+        K: Key,
+    {
+        let raw_key = namespaced_key(namespace, key)?;
+        self.backend.delete(raw_key)
+    }
+
+    /// Atomically writes a batch of encoded values, where a `None` value deletes the key
+    ///
+    /// Either all records reach the backend or none of them do.
+    pub(crate) fn write_batch<'a, K, I>(&mut self, namespace: &[u8], records: I) -> Result<()>
+    where
+        K: Key + 'a,
+        I: IntoIterator<Item = (&'a K, Option<Cow<'a, [u8]>>)>,
+    {
+        let mut batch = Vec::new();
+        for (key, raw_value) in records {
+            batch.push((namespaced_key(namespace, key)?, raw_value));
+        }
+        self.write_raw_batch(namespace, batch)
+    }
+
+    /// Same as [RawStore::write_batch] but for keys that already carry the namespace prefix
+    fn write_raw_batch<V, I>(&mut self, namespace: &[u8], records: I) -> Result<()>
+    where
+        V: AsRef<[u8]>,
+        I: IntoIterator<Item = (Vec<u8>, Option<V>)>,
+    {
+        let mut batch = Vec::new();
+        for (raw_key, raw_value) in records {
+            let raw_value = match raw_value {
+                Some(raw_value) => Some(
+                    self.encode_value(namespace, &raw_key, raw_value.as_ref())?
+                        .into_owned(),
+                ),
+                None => None,
+            };
+            batch.push((raw_key, raw_value));
+        }
+        self.backend.write_batch(batch)
+    }
+
+    /// Insert a record whose key is already encoded, e.g., by [OrderedKey]
+    ///
+    /// [OrderedKey]: crate::data::OrderedKey
+    #[inline]
+    pub(crate) fn put_bytes(
+        &mut self,
+        namespace: &[u8],
+        key: &[u8],
+        raw_value: &[u8],
+    ) -> Result<()> {
+        let raw_key = namespaced_bytes(namespace, key);
+        let raw_value = self.encode_value(namespace, &raw_key, raw_value)?;
+        self.backend.put(raw_key, raw_value)
+    }
+
+    /// Insert a batch of records whose keys are already encoded
+    #[inline]
+    pub(crate) fn put_bytes_batch<I>(&mut self, namespace: &[u8], records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let mut batch = Vec::new();
+        for (key, raw_value) in records {
+            let raw_key = namespaced_bytes(namespace, &key);
+            let raw_value = self
+                .encode_value(namespace, &raw_key, &raw_value)?
+                .into_owned();
+            batch.push((raw_key, raw_value));
+        }
+        self.backend.put_batch(batch)
+    }
+
+    /// Atomically writes a batch of records whose keys are already encoded,
+    /// where a `None` value deletes the key
+    #[inline]
+    pub(crate) fn write_bytes_batch<I>(&mut self, namespace: &[u8], records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    {
+        let records = records
+            .into_iter()
+            .map(|(key, raw_value)| (namespaced_bytes(namespace, &key), raw_value));
+        self.write_raw_batch(namespace, records)
+    }
+
+    /// Fetch the encoded Value of an already encoded key
+    #[inline]
+    pub(crate) fn get_bytes(&self, namespace: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let raw_key = namespaced_bytes(namespace, key);
+        self.get_namespaced(namespace, &raw_key)
+    }
+
+    /// Scans up to `limit` records of a namespace in key order, starting at `from`
+    ///
+    /// Returns the encoded keys without the namespace prefix together with their
+    /// encoded values.
+    pub(crate) fn scan(
+        &self,
+        namespace: &[u8],
+        from: &[u8],
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let prefix = namespace_prefix(namespace, 0);
+        let start = namespaced_bytes(namespace, from);
+        let mut records = Vec::new();
+        for record in self.backend.iter_from(&start) {
+            let (raw_key, bytes) = record?;
+            if records.len() == limit || !raw_key.starts_with(&prefix) {
+                break;
+            }
+            let raw_value = self.decode_value(namespace, &raw_key, bytes.into_vec())?;
+            records.push((raw_key[prefix.len()..].to_vec(), raw_value));
+        }
+        Ok(records)
+    }
+
+    /// Same as [RawStore::scan] but only returns the encoded keys
+    ///
+    /// Values are neither decrypted nor decompressed.
+    pub(crate) fn scan_keys(
+        &self,
+        namespace: &[u8],
+        from: &[u8],
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        let prefix = namespace_prefix(namespace, 0);
+        let start = namespaced_bytes(namespace, from);
+        let mut keys = Vec::new();
+        for record in self.backend.iter_from(&start) {
+            let (raw_key, _) = record?;
+            if keys.len() == limit || !raw_key.starts_with(&prefix) {
+                break;
+            }
+            keys.push(raw_key[prefix.len()..].to_vec());
+        }
+        Ok(keys)
+    }
+
+    #[inline]
+    fn get_namespaced(&self, namespace: &[u8], raw_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.backend.get(raw_key)? {
+            Some(bytes) => self.decode_value(namespace, raw_key, bytes).map(Some),
+            None => Ok(None),
+        }
+    }
     #[inline]
     pub fn checkpoint(&mut self) -> Result<()> {
         self.backend.checkpoint()
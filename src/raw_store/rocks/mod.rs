@@ -1,6 +1,7 @@
-use crate::data::{Key, Value};
 use crate::error::*;
-use rocksdb::{checkpoint::Checkpoint, WriteBatch, WriteOptions, DB};
+use rocksdb::{
+    checkpoint::Checkpoint, DBIterator, Direction, IteratorMode, WriteBatch, WriteOptions, DB,
+};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -47,17 +48,40 @@ impl Backend {
             .map_err(|e| BrittMarieError::Insert(e.to_string()))
     }
     #[inline(always)]
+    pub fn delete(&self, key: impl AsRef<[u8]>) -> Result<()> {
+        self.db
+            .delete_opt(key.as_ref(), &self.write_opts)
+            .map_err(|e| BrittMarieError::Delete(e.to_string()))
+    }
+    #[inline(always)]
     pub fn put_batch<K, V, I>(&self, kv_pairs: I) -> Result<()>
     where
-        K: Key,
-        V: Value,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
         I: IntoIterator<Item = (K, V)>,
     {
         let mut wb = WriteBatch::default();
         for (key, value) in kv_pairs {
-            let raw_key = key.into_raw()?;
-            let raw_value = value.into_raw()?;
-            wb.put(raw_key, raw_value);
+            wb.put(key, value);
+        }
+
+        self.db
+            .write_opt(wb, &self.write_opts)
+            .map_err(|e| BrittMarieError::Insert(e.to_string()))
+    }
+
+    /// Atomically writes a batch of records, where a `None` value deletes the key
+    #[inline(always)]
+    pub fn write_batch<I>(&self, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    {
+        let mut wb = WriteBatch::default();
+        for (key, value) in records {
+            match value {
+                Some(value) => wb.put(key, value),
+                None => wb.delete(key),
+            }
         }
 
         self.db
@@ -71,6 +95,19 @@ impl Backend {
             .get(key.as_ref())
             .map_err(|e| BrittMarieError::Read(e.to_string()))
     }
+    /// Iterates over all records in key order
+    #[inline(always)]
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(self.db.iterator(IteratorMode::Start))
+    }
+    /// Iterates over the records starting at `key` in key order
+    #[inline(always)]
+    pub fn iter_from<'a>(&'a self, key: &[u8]) -> Iter<'a> {
+        Iter::new(
+            self.db
+                .iterator(IteratorMode::From(key, Direction::Forward)),
+        )
+    }
     #[inline(always)]
     pub fn checkpoint(&mut self) -> Result<()> {
         let path = self.path.join(self.checkpoint_counter.to_string());
@@ -89,3 +126,40 @@ impl Backend {
         Ok(())
     }
 }
+
+/// Iterator over the records of a [Backend] in key order
+///
+/// RocksDB iterators end early when they hit an error, so the status is
+/// checked once the records run out and a failure is yielded as a last item.
+pub struct Iter<'a> {
+    inner: DBIterator<'a>,
+    done: bool,
+}
+
+impl<'a> Iter<'a> {
+    #[inline]
+    fn new(inner: DBIterator<'a>) -> Self {
+        Iter { inner, done: false }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(Box<[u8]>, Box<[u8]>)>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next() {
+            Some(record) => Some(Ok(record)),
+            None => {
+                self.done = true;
+                self.inner
+                    .status()
+                    .err()
+                    .map(|e| Err(BrittMarieError::Read(e.to_string())))
+            }
+        }
+    }
+}
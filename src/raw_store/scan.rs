@@ -0,0 +1,88 @@
+use super::RawStore;
+use crate::error::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::rc::Rc;
+
+/// Scan over the records of a namespace in encoded key order
+///
+/// Records are fetched in batches so that the RawStore is only borrowed
+/// while a batch is read. Yields encoded keys without the namespace prefix
+/// together with their encoded values.
+pub(crate) struct RawScan {
+    raw_store: Rc<RefCell<RawStore>>,
+    namespace: Vec<u8>,
+    /// Encoded key to continue from, `None` once the scan is exhausted
+    next: Option<Vec<u8>>,
+    /// Encoded end of the scan
+    end: Bound<Vec<u8>>,
+    batch_size: usize,
+    buffer: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RawScan {
+    pub fn new(
+        raw_store: Rc<RefCell<RawStore>>,
+        namespace: Vec<u8>,
+        from: Vec<u8>,
+        end: Bound<Vec<u8>>,
+        batch_size: usize,
+    ) -> Self {
+        RawScan {
+            raw_store,
+            namespace,
+            next: Some(from),
+            end,
+            batch_size,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    fn refill(&mut self) -> Result<()> {
+        let from = match self.next.take() {
+            Some(from) => from,
+            None => return Ok(()),
+        };
+        let records = self
+            .raw_store
+            .borrow()
+            .scan(&self.namespace, &from, self.batch_size)?;
+        if records.len() == self.batch_size {
+            self.next = records.last().map(|(key, _)| successor(key.clone()));
+        }
+        for (key, raw) in records {
+            let in_range = match &self.end {
+                Bound::Included(end) => key <= *end,
+                Bound::Excluded(end) => key < *end,
+                Bound::Unbounded => true,
+            };
+            if !in_range {
+                self.next = None;
+                break;
+            }
+            self.buffer.push_back((key, raw));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for RawScan {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            if let Err(err) = self.refill() {
+                return Some(Err(err));
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+/// Returns the smallest encoded key that is greater than `key`
+#[inline]
+pub(crate) fn successor(mut key: Vec<u8>) -> Vec<u8> {
+    key.push(0);
+    key
+}
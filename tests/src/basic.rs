@@ -20,7 +20,7 @@ fn streaming_state_test() {
     let capacity = 128;
     let modificaton_factor: f32 = 0.6;
     let counters: HashIndex<u64, u64> =
-        HashIndex::new(capacity, modificaton_factor, raw_store.clone());
+        HashIndex::new("_counters", capacity, modificaton_factor, raw_store.clone());
 
     let mut state = StreamingState {
         watermark,
@@ -37,3 +37,33 @@ fn streaming_state_test() {
     assert_eq!(state.counters().get(&10), Some(&1));
     assert_eq!(state.checkpoint(raw_store).is_ok(), true);
 }
+
+#[test]
+fn warm_up_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    {
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut counters: HashIndex<u64, u64> =
+            HashIndex::new("_counters", 64, 0.6, raw_store).with_hot_keys(4);
+        for i in 0..256u64 {
+            counters.put(i, i * 2);
+        }
+        for _ in 0..10 {
+            for i in 0..4u64 {
+                assert_eq!(counters.get(&i), Some(&(i * 2)));
+            }
+        }
+        assert!(counters.persist().is_ok());
+    }
+
+    // A fresh RawStore over the same path preloads the recorded hot keys
+    let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+    let mut counters: HashIndex<u64, u64> =
+        HashIndex::new("_counters", 64, 0.6, raw_store).with_hot_keys(4);
+    assert_eq!(counters.warm_up().unwrap(), 4);
+    assert_eq!(counters.len(), 4);
+    for i in 0..4u64 {
+        assert_eq!(counters.get(&i), Some(&(i * 2)));
+    }
+}
@@ -0,0 +1,134 @@
+use britt_marie::prost::Message;
+use britt_marie::{BrittMarieData, HashIndex, HashOps, RawStore};
+use std::cell::RefCell;
+use std::rc::Rc;
+use tempfile::tempdir;
+
+#[derive(BrittMarieData, Clone, PartialEq)]
+pub struct Reading {
+    sensor: String,
+    value: f64,
+    delta: i64,
+    samples: Vec<u32>,
+    tags: Vec<String>,
+    raw: Vec<u8>,
+    location: Option<Location>,
+    alert: Option<u64>,
+}
+
+#[derive(BrittMarieData, Clone, PartialEq, Eq, Hash)]
+pub struct Location(i32, i32);
+
+#[derive(BrittMarieData, Clone, PartialEq)]
+pub enum Event {
+    Heartbeat,
+    Reading(Reading),
+    Moved { from: Location, to: Location },
+}
+
+#[derive(BrittMarieData, Clone, PartialEq)]
+pub struct AccountV1 {
+    #[britt_marie(tag = 1)]
+    id: u64,
+    #[britt_marie(tag = 2)]
+    balance: i64,
+}
+
+#[derive(BrittMarieData, Clone, PartialEq)]
+#[britt_marie(reserved = "2")]
+pub struct AccountV2 {
+    #[britt_marie(tag = 1)]
+    id: u64,
+    #[britt_marie(tag = 3)]
+    owner: String,
+    #[britt_marie(skip)]
+    cached: u32,
+}
+
+fn roundtrip<T: Message + Default>(value: &T) -> T {
+    let mut buf = Vec::with_capacity(value.encoded_len());
+    value.encode(&mut buf).unwrap();
+    assert_eq!(buf.len(), value.encoded_len());
+    T::decode(&buf[..]).unwrap()
+}
+
+fn reading() -> Reading {
+    Reading {
+        sensor: String::from("s1"),
+        value: 21.5,
+        delta: -3,
+        samples: vec![1, 2, 300],
+        tags: vec![String::from("a"), String::from("b")],
+        raw: vec![0, 1, 2],
+        location: Some(Location(-10, 20)),
+        alert: Some(0),
+    }
+}
+
+#[test]
+fn struct_roundtrip_test() {
+    let value = reading();
+    assert_eq!(roundtrip(&value), value);
+    assert_eq!(roundtrip(&Reading::default()), Reading::default());
+    assert_eq!(Reading::default().encoded_len(), 0);
+}
+
+#[test]
+fn enum_roundtrip_test() {
+    assert_eq!(Event::default(), Event::Heartbeat);
+    for event in vec![
+        Event::Heartbeat,
+        Event::Reading(reading()),
+        Event::Moved {
+            from: Location(0, 0),
+            to: Location(5, -5),
+        },
+    ] {
+        assert_eq!(roundtrip(&event), event);
+    }
+}
+
+#[test]
+fn versioned_tags_test() {
+    let v1 = AccountV1 {
+        id: 7,
+        balance: 100,
+    };
+    let mut buf = Vec::new();
+    v1.encode(&mut buf).unwrap();
+    // The retired balance tag is skipped and the new field takes its default
+    let v2 = AccountV2::decode(&buf[..]).unwrap();
+    assert_eq!(v2.id, 7);
+    assert_eq!(v2.owner, "");
+
+    let v2 = AccountV2 {
+        id: 8,
+        owner: String::from("max"),
+        cached: 5,
+    };
+    let decoded = roundtrip(&v2);
+    assert_eq!(decoded.owner, "max");
+    assert_eq!(decoded.cached, 0);
+    // Readers of the old version skip the new owner tag
+    let mut buf = Vec::new();
+    v2.encode(&mut buf).unwrap();
+    let v1 = AccountV1::decode(&buf[..]).unwrap();
+    assert_eq!(v1.id, 8);
+    assert_eq!(v1.balance, 0);
+}
+
+#[test]
+fn derived_index_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+    let mut index: HashIndex<Location, Reading> = HashIndex::new("_readings", 4, 0.5, raw_store);
+    for i in 0..64 {
+        let mut value = reading();
+        value.delta = i as i64;
+        index.put(Location(i, -i), value);
+    }
+    for i in 0..64 {
+        assert_eq!(index.get(&Location(i, -i)).map(|r| r.delta), Some(i as i64));
+    }
+}
@@ -0,0 +1,85 @@
+use britt_marie::{
+    Compression, HashIndex, HashOps, IndexOps, KeyRing, RawStore, ValueIndex, ValueOps,
+    XChaChaCipher,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+use tempfile::tempdir;
+
+#[test]
+fn encrypted_store_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let mut raw_store = RawStore::new(path);
+    raw_store.set_cipher(XChaChaCipher::new(KeyRing::new(1, [3u8; 32])));
+    raw_store.set_compression("_users", Compression::Lz4).unwrap();
+    let raw_store = Rc::new(RefCell::new(raw_store));
+
+    let mut users: HashIndex<u64, String> = HashIndex::new("_users", 4, 0.5, raw_store.clone());
+    let mut epoch: ValueIndex<u64> = ValueIndex::new("_epoch", raw_store.clone());
+    for i in 0..64 {
+        users.put(i, format!("user-{}", i));
+    }
+    epoch.put(5);
+    assert!(users.persist().is_ok());
+    assert!(epoch.persist().is_ok());
+    assert!(raw_store.borrow_mut().checkpoint().is_ok());
+
+    let users: HashIndex<u64, String> = HashIndex::new("_users", 4, 0.5, raw_store.clone());
+    for i in 0..64 {
+        assert_eq!(users.get(&i), Some(&format!("user-{}", i)));
+    }
+    let value: Option<u64> = raw_store.borrow().get(b"_epoch", &()).unwrap();
+    assert_eq!(value, Some(5));
+}
+
+#[test]
+fn key_rotation_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let mut raw_store = RawStore::new(path);
+    let mut key_ring = KeyRing::new(1, [1u8; 32]);
+    raw_store.set_cipher(XChaChaCipher::new(key_ring.clone()));
+    for i in 0..2048u64 {
+        assert!(raw_store.put(b"_ids", &i, &i).is_ok());
+    }
+
+    // Old values stay readable after a rotation until they are rewritten
+    key_ring.rotate(2, [2u8; 32]);
+    raw_store.set_cipher(XChaChaCipher::new(key_ring.clone()));
+    assert!(raw_store.put(b"_ids", &0u64, &100u64).is_ok());
+    let value: Option<u64> = raw_store.get(b"_ids", &1u64).unwrap();
+    assert_eq!(value, Some(1));
+    assert_eq!(raw_store.reencrypt().unwrap(), 2047);
+    assert_eq!(raw_store.reencrypt().unwrap(), 0);
+
+    key_ring.retire(1);
+    raw_store.set_cipher(XChaChaCipher::new(key_ring));
+    for i in 1..2048u64 {
+        let value: Option<u64> = raw_store.get(b"_ids", &i).unwrap();
+        assert_eq!(value, Some(i));
+    }
+    let value: Option<u64> = raw_store.get(b"_ids", &0u64).unwrap();
+    assert_eq!(value, Some(100));
+}
+
+#[test]
+fn encrypt_existing_store_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let mut raw_store = RawStore::new(path);
+    for i in 0..64u64 {
+        assert!(raw_store.put(b"_ids", &i, &i).is_ok());
+    }
+
+    // Plaintext values stay readable and are encrypted by reencrypt
+    raw_store.set_cipher(XChaChaCipher::new(KeyRing::new(1, [1u8; 32])));
+    let value: Option<u64> = raw_store.get(b"_ids", &1u64).unwrap();
+    assert_eq!(value, Some(1));
+    assert_eq!(raw_store.reencrypt().unwrap(), 64);
+    assert_eq!(raw_store.reencrypt().unwrap(), 0);
+    for i in 0..64u64 {
+        let value: Option<u64> = raw_store.get(b"_ids", &i).unwrap();
+        assert_eq!(value, Some(i));
+    }
+}
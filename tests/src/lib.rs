@@ -1,2 +1,10 @@
 #[cfg(test)]
 mod basic;
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod encryption;
+#[cfg(test)]
+mod namespace;
+#[cfg(test)]
+mod ordered;
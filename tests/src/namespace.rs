@@ -0,0 +1,114 @@
+use britt_marie::{BrittMarieData, Compression, HashIndex, HashOps, IndexOps, RawStore};
+use std::cell::RefCell;
+use std::rc::Rc;
+use tempfile::tempdir;
+
+#[derive(BrittMarieData, Clone, PartialEq)]
+pub struct Series {
+    name: String,
+    points: Vec<f64>,
+}
+
+fn series(i: u64) -> Series {
+    Series {
+        name: format!("series-{}", i),
+        points: (0..128).map(|p| (p % 8) as f64).collect(),
+    }
+}
+
+#[test]
+fn isolated_namespaces_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+    let mut first: HashIndex<u64, u64> = HashIndex::new("_first", 4, 0.5, raw_store.clone());
+    let mut second: HashIndex<u64, u64> = HashIndex::new("_second", 4, 0.5, raw_store.clone());
+    for i in 0..64 {
+        first.put(i, i);
+        second.put(i, i + 1000);
+    }
+    assert!(first.persist().is_ok());
+    assert!(second.persist().is_ok());
+
+    let first: HashIndex<u64, u64> = HashIndex::new("_first", 4, 0.5, raw_store.clone());
+    let second: HashIndex<u64, u64> = HashIndex::new("_second", 4, 0.5, raw_store);
+    for i in 0..64 {
+        assert_eq!(first.get(&i), Some(&i));
+        assert_eq!(second.get(&i), Some(&(i + 1000)));
+    }
+}
+
+#[test]
+fn compressed_namespace_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+    let mut index: HashIndex<u64, Series> =
+        HashIndex::new("_series", 4, 0.5, raw_store.clone()).with_compression(Compression::Lz4);
+    for i in 0..64 {
+        index.put(i, series(i));
+    }
+    assert!(index.persist().is_ok());
+
+    // Values written with LZ4 stay readable after switching codec
+    let samples: Vec<Vec<u8>> = (0..64)
+        .map(|i| format!("series-{}", i).into_bytes())
+        .collect();
+    let dictionary = Compression::train_dictionary(&samples, 512).unwrap();
+    let mut index: HashIndex<u64, Series> = HashIndex::new("_series", 4, 0.5, raw_store.clone())
+        .with_compression(Compression::zstd_with_dictionary(3, dictionary));
+    for i in 0..64 {
+        assert_eq!(index.get(&i), Some(&series(i)));
+    }
+    for i in 64..128 {
+        index.put(i, series(i));
+    }
+    assert!(index.persist().is_ok());
+
+    let raw_store = raw_store.borrow();
+    for i in 0..128 {
+        let value: Option<Series> = raw_store.get(b"_series", &i).unwrap();
+        assert_eq!(value, Some(series(i)));
+    }
+}
+
+#[test]
+fn compression_on_existing_namespace_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    {
+        let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+        let mut index: HashIndex<u64, Series> =
+            HashIndex::new("_series", 4, 0.5, raw_store.clone());
+        for i in 0..64 {
+            index.put(i, series(i));
+        }
+        assert!(index.persist().is_ok());
+
+        // Namespaces that hold uncompressed values cannot be compressed
+        assert!(raw_store
+            .borrow_mut()
+            .set_compression("_series", Compression::Lz4)
+            .is_err());
+        for i in 0..64 {
+            assert_eq!(index.get(&i), Some(&series(i)));
+        }
+
+        let mut index: HashIndex<u64, Series> =
+            HashIndex::new("_compressed", 4, 0.5, raw_store).with_compression(Compression::Lz4);
+        for i in 0..64 {
+            index.put(i, series(i));
+        }
+        assert!(index.persist().is_ok());
+    }
+
+    // Compressed namespaces can be compressed again after a restart
+    let mut raw_store = RawStore::new(path);
+    assert!(raw_store
+        .set_compression("_compressed", Compression::Lz4)
+        .is_ok());
+    for i in 0..64 {
+        let value: Option<Series> = raw_store.get(b"_compressed", &i).unwrap();
+        assert_eq!(value, Some(series(i)));
+    }
+}
@@ -0,0 +1,35 @@
+use britt_marie::{BrittMarie, IndexOps, OrderedIndex, OrderedOps, RawStore};
+use std::cell::RefCell;
+use std::rc::Rc;
+use tempfile::tempdir;
+
+#[derive(BrittMarie)]
+pub struct WindowState {
+    windows: OrderedIndex<u64, u64>,
+}
+
+#[test]
+fn ordered_state_test() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let raw_store = Rc::new(RefCell::new(RawStore::new(path)));
+    let windows: OrderedIndex<u64, u64> = OrderedIndex::new("_windows", 32, raw_store.clone());
+    let mut state = WindowState { windows };
+
+    for ts in (0..10_000u64).step_by(100) {
+        state.windows().put(ts, 0);
+    }
+    for ts in 0..10_000u64 {
+        assert!(state.windows().rmw(&(ts - ts % 100), |count| *count += 1));
+    }
+    assert_eq!(state.checkpoint(raw_store.clone()).is_ok(), true);
+
+    // Fire all windows that end before the watermark
+    let fired: Vec<(u64, u64)> = state.windows().range(..5000).collect();
+    assert_eq!(fired.len(), 50);
+    assert!(fired.iter().all(|&(_, count)| count == 100));
+    assert!(fired.windows(2).all(|w| w[0].0 < w[1].0));
+
+    let mut restored: OrderedIndex<u64, u64> = OrderedIndex::new("_windows", 32, raw_store);
+    assert_eq!(restored.range(..).count(), 100);
+}